@@ -0,0 +1 @@
+DROP TABLE ltiOutcomes
\ No newline at end of file
@@ -0,0 +1,10 @@
+CREATE TABLE ltiOutcomes
+(
+    username    VARCHAR(128)    NOT NULL,
+    course      VARCHAR(128)    NOT NULL,
+    consumerKey VARCHAR(128)    NOT NULL,
+    serviceUrl  TEXT            NOT NULL,
+    sourcedid   TEXT            NOT NULL,
+    lastGrade   FLOAT                       DEFAULT NULL,
+    PRIMARY KEY (username, course)
+)
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE lti13Logins
\ No newline at end of file
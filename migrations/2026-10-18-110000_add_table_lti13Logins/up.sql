@@ -0,0 +1,8 @@
+CREATE TABLE lti13Logins
+(
+    state           VARCHAR(128)    NOT NULL    PRIMARY KEY,
+    nonce           VARCHAR(128)    NOT NULL,
+    issuer          TEXT            NOT NULL,
+    clientId        TEXT            NOT NULL,
+    expirationTime  BIGINT          NOT NULL
+)
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE ltiNonces
\ No newline at end of file
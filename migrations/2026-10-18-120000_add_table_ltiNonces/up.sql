@@ -0,0 +1,7 @@
+CREATE TABLE ltiNonces
+(
+    consumerKey     VARCHAR(128)    NOT NULL,
+    nonce           VARCHAR(128)    NOT NULL,
+    expirationTime  BIGINT          NOT NULL,
+    PRIMARY KEY (consumerKey, nonce)
+)
\ No newline at end of file
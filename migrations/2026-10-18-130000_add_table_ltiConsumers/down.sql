@@ -0,0 +1 @@
+DROP TABLE ltiConsumers
\ No newline at end of file
@@ -0,0 +1,8 @@
+CREATE TABLE ltiConsumers
+(
+    consumerKey     VARCHAR(128)    NOT NULL                    PRIMARY KEY,
+    secret          TEXT            NOT NULL,
+    enabled         BOOLEAN         NOT NULL    DEFAULT true,
+    defaultCourse   VARCHAR(128)                DEFAULT NULL,
+    usernamePrefix  VARCHAR(128)    NOT NULL    DEFAULT ''
+)
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE courseRoles
\ No newline at end of file
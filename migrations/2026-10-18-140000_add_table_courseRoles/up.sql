@@ -0,0 +1,7 @@
+CREATE TABLE courseRoles
+(
+    username    VARCHAR(128)    NOT NULL,
+    course      VARCHAR(128)    NOT NULL,
+    role        VARCHAR(32)     NOT NULL    DEFAULT 'student',
+    PRIMARY KEY (username, course)
+)
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE users
+    DROP COLUMN isAdmin
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE sessions
+    ADD scope VARCHAR(32) DEFAULT NULL
\ No newline at end of file
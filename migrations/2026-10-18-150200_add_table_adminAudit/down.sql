@@ -0,0 +1 @@
+DROP TABLE adminAudit
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE adminAudit
+(
+    id          INT             NOT NULL    AUTO_INCREMENT,
+    admin       VARCHAR(128)    NOT NULL,
+    method      VARCHAR(16)     NOT NULL,
+    route       TEXT            NOT NULL,
+    timestamp   BIGINT          NOT NULL,
+    PRIMARY KEY (id)
+)
\ No newline at end of file
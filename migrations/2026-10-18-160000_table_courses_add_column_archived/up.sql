@@ -0,0 +1,2 @@
+ALTER TABLE courses
+    ADD archived BOOLEAN NOT NULL DEFAULT false
\ No newline at end of file
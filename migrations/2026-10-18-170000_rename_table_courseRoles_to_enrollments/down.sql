@@ -0,0 +1 @@
+RENAME TABLE enrollments TO courseRoles
\ No newline at end of file
@@ -0,0 +1 @@
+RENAME TABLE courseRoles TO enrollments
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE enrollments
+    DROP COLUMN enrolledAt
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE enrollments
+    ADD enrolledAt BIGINT NOT NULL DEFAULT 0
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Enrollments of existing users can't be told apart from new ones, so keep them
+DO 0
\ No newline at end of file
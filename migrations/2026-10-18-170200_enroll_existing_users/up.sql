@@ -0,0 +1,6 @@
+-- Course membership used to be implicit: enroll everyone who has logged in to
+-- or submitted in a course before
+INSERT IGNORE INTO enrollments (username, course, role, enrolledAt)
+    SELECT username, courseName, 'student', UNIX_TIMESTAMP() FROM sessions WHERE scope IS NULL
+    UNION
+    SELECT user, course, 'student', UNIX_TIMESTAMP() FROM submissions
\ No newline at end of file
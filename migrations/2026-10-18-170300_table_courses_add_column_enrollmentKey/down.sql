@@ -0,0 +1,2 @@
+ALTER TABLE courses
+    DROP COLUMN enrollmentKey
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE courses
+    ADD enrollmentKey VARCHAR(128) DEFAULT NULL
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE announcements
\ No newline at end of file
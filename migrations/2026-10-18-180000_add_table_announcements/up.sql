@@ -0,0 +1,9 @@
+CREATE TABLE announcements
+(
+    id          INT             NOT NULL    AUTO_INCREMENT,
+    course      VARCHAR(128)    NOT NULL,
+    author      VARCHAR(128)    NOT NULL,
+    message     TEXT            NOT NULL,
+    timestamp   BIGINT          NOT NULL,
+    PRIMARY KEY (id)
+)
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE reevaluations
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE reevaluations
+(
+    id          INT             NOT NULL    AUTO_INCREMENT,
+    course      VARCHAR(128)    NOT NULL,
+    taskid      INT                         DEFAULT NULL,
+    admin       VARCHAR(128)    NOT NULL,
+    timestamp   BIGINT          NOT NULL,
+    PRIMARY KEY (id)
+)
\ No newline at end of file
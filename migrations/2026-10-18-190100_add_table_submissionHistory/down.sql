@@ -0,0 +1 @@
+DROP TABLE submissionHistory
\ No newline at end of file
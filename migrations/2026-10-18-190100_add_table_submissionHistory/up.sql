@@ -0,0 +1,13 @@
+CREATE TABLE submissionHistory
+(
+    id              INT             NOT NULL    AUTO_INCREMENT,
+    submission      INT             NOT NULL,
+    reevaluation    INT             NOT NULL,
+    resultType      VARCHAR(128)    NOT NULL,
+    simplified      TEXT            NOT NULL,
+    details         TEXT            NOT NULL,
+    score           FLOAT           NOT NULL,
+    PRIMARY KEY (id),
+    INDEX (submission),
+    INDEX (reevaluation)
+)
\ No newline at end of file
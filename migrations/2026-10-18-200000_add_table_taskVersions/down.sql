@@ -0,0 +1 @@
+DROP TABLE taskVersions
\ No newline at end of file
@@ -0,0 +1,15 @@
+CREATE TABLE taskVersions
+(
+    id              INT             NOT NULL    AUTO_INCREMENT,
+    taskid          INT             NOT NULL,
+    version         INT             NOT NULL,
+    taskDescription TEXT            NOT NULL,
+    solution        TEXT            NOT NULL,
+    lang            TEXT            NOT NULL,
+    tests           TEXT            NOT NULL,
+    courses         TEXT            NOT NULL,
+    author          VARCHAR(255)    NOT NULL,
+    timestamp       BIGINT          NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (taskid, version)
+)
\ No newline at end of file
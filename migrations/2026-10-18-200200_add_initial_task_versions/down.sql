@@ -0,0 +1 @@
+DELETE FROM taskVersions WHERE author = 'migration'
\ No newline at end of file
@@ -0,0 +1,14 @@
+-- Existing tasks become version 1, including their course mappings in the
+-- format of the admin task routes
+SET SESSION group_concat_max_len = 16777216;
+INSERT INTO taskVersions (taskid, version, taskDescription, solution, lang, tests, courses, author, timestamp)
+    SELECT tasks.taskid, 1, taskDescription, solution, lang, tests,
+        CONCAT('[', IFNULL(GROUP_CONCAT(CONCAT(
+            '{"courseName":', JSON_QUOTE(course),
+            ',"tags":', tags,
+            ',"orderBy":', orderBy,
+            ',"prerequisites":', prerequisites, '}'
+        )), ''), ']'),
+        'migration', UNIX_TIMESTAMP()
+    FROM tasks LEFT JOIN courseTask ON courseTask.taskid = tasks.taskid
+    GROUP BY tasks.taskid
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE submissions
+    DROP COLUMN taskVersion
\ No newline at end of file
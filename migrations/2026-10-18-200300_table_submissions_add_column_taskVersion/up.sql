@@ -0,0 +1,4 @@
+-- Version of the task the submission was last evaluated against, NULL for
+-- submissions evaluated before tasks were versioned
+ALTER TABLE submissions
+    ADD taskVersion INT NULL
\ No newline at end of file
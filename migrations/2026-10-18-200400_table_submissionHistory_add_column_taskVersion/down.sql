@@ -0,0 +1,2 @@
+ALTER TABLE submissionHistory
+    DROP COLUMN taskVersion
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE submissionHistory
+    ADD taskVersion INT NULL
\ No newline at end of file
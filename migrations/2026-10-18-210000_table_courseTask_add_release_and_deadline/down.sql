@@ -0,0 +1,4 @@
+ALTER TABLE courseTask
+    DROP COLUMN visibleFrom,
+    DROP COLUMN deadline,
+    DROP COLUMN lateUntil
\ No newline at end of file
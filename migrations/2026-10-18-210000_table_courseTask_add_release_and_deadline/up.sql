@@ -0,0 +1,5 @@
+-- Unix timestamps, NULL if the task is always visible / has no deadline
+ALTER TABLE courseTask
+    ADD visibleFrom BIGINT  NULL,
+    ADD deadline    BIGINT  NULL,
+    ADD lateUntil   BIGINT  NULL
\ No newline at end of file
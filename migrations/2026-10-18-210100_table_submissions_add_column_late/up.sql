@@ -0,0 +1,2 @@
+ALTER TABLE submissions
+    ADD late BOOLEAN NOT NULL DEFAULT false
\ No newline at end of file
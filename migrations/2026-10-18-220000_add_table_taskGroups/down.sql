@@ -0,0 +1 @@
+DROP TABLE taskGroups
\ No newline at end of file
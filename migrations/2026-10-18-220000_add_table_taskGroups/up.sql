@@ -0,0 +1,12 @@
+CREATE TABLE taskGroups
+(
+    id              INT             NOT NULL    AUTO_INCREMENT,
+    course          VARCHAR(128)    NOT NULL,
+    title           TEXT            NOT NULL,
+    description     TEXT            NOT NULL,
+    orderBy         INT             NOT NULL    DEFAULT 0,
+    visibleFrom     BIGINT          NULL,
+    passThreshold   FLOAT           NOT NULL    DEFAULT 1,
+    PRIMARY KEY (id),
+    INDEX (course)
+)
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE courseTask
+    DROP COLUMN groupId
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE courseTask
+    ADD groupId INT NULL
\ No newline at end of file
@@ -0,0 +1,95 @@
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use serde_json::Value;
+use diesel::prelude::*;
+use crate::schema::{adminAudit, users};
+use crate::auth::guards;
+use crate::DbConn;
+use crate::error::Error;
+
+/// Scope of sessions that are admin tokens. Admin tokens are accepted by the
+/// Admin guard only, never as regular user sessions.
+pub const ADMIN_SCOPE: &str = "admin";
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub admin: String,
+    pub method: String,
+    pub route: String,
+    pub timestamp: i64
+}
+
+/// Issues a permanent admin token for the logged in user. The token is managed
+/// like other API tokens (see api_token), but only valid for admin routes.
+#[post("/auth/adminToken/<token_name>")]
+pub fn post_admin_token(user: guards::Session, token_name: String, conn: DbConn) -> Result<Json<Value>, Error> {
+    if !is_admin(&conn, &user.name)? {
+        return Err(Status::Forbidden.into());
+    }
+
+    Ok(Json(json!({
+        "adminToken": super::create_scoped_session(&conn, &user.name, &user.course, &Some(token_name), Some(ADMIN_SCOPE))?
+    })))
+}
+
+/// Grants or revokes admin rights, body: {"admin": true}. Revoking also
+/// invalidates all admin tokens of the user.
+#[put("/admin/users/<username>/admin", data = "<data>")]
+pub fn route_put_admin(_admin: guards::Admin, username: String, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let admin = data["admin"].as_bool()
+        .ok_or(Status::BadRequest)?;
+
+    let updated = diesel::update(users::table.filter(users::username.eq(&username)))
+        .set(users::isAdmin.eq(admin))
+        .execute(&*conn)?;
+
+    if updated == 0 {
+        return Err(Status::NotFound.into());
+    }
+
+    if !admin {
+        use crate::schema::sessions;
+        diesel::delete(sessions::table.filter(sessions::username.eq(&username)))
+            .filter(sessions::scope.eq(ADMIN_SCOPE))
+            .execute(&*conn)?;
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Returns the audit log, newest entries first. Optional query parameters:
+/// `admin` (only entries of this admin) and `limit` (default 100).
+#[get("/admin/audit?<admin>&<limit>")]
+pub fn route_get_audit(_admin: guards::Admin, admin: Option<String>, limit: Option<i64>, conn: DbConn) -> Result<Json<Vec<AuditEntry>>, Error> {
+    let mut query = adminAudit::table.into_boxed();
+    if let Some(admin) = admin {
+        query = query.filter(adminAudit::admin.eq(admin));
+    }
+
+    Ok(Json(query.order(adminAudit::id.desc())
+        .limit(limit.unwrap_or(100))
+        .load::<AuditEntry>(&*conn)?))
+}
+
+pub fn is_admin(conn: &MysqlConnection, username: &str) -> Result<bool, Error> {
+    Ok(users::table.filter(users::username.eq(username))
+        .select(users::isAdmin)
+        .first::<bool>(conn)
+        .optional()?
+        .unwrap_or(false))
+}
+
+/// Records that an admin called a privileged route.
+pub fn audit(conn: &MysqlConnection, admin: &str, method: &str, route: &str) -> Result<(), Error> {
+    diesel::insert_into(adminAudit::table)
+        .values((
+            adminAudit::admin.eq(admin),
+            adminAudit::method.eq(method),
+            adminAudit::route.eq(route),
+            adminAudit::timestamp.eq(crate::tools::epoch())
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
@@ -1,16 +1,22 @@
 use rocket::request::{Request, FromRequest, Outcome};
 use rocket::http::Status;
+use rocket::outcome::try_outcome;
 use diesel::prelude::*;
-use crate::SETTINGS;
+use crate::{SETTINGS, DbConn};
+use crate::error::Error;
+use super::roles::Role;
 
+/// A logged in user, regardless of enrollment in the session's course.
+/// Use User for everything course related.
 #[derive(Debug)]
-pub struct User {
+pub struct Session {
     pub name: String,
-    pub course: String
+    pub course: String,
+    pub token: String
 }
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for User {
+impl<'r> FromRequest<'r> for Session {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
@@ -22,28 +28,105 @@ impl<'r> FromRequest<'r> for User {
             token.unwrap()
         };
 
-        if !super::check_and_refresh_token(&token) {
-            return Outcome::Failure((Status::Unauthorized, ()));
+        let conn = try_outcome!(req.guard::<DbConn>().await);
+
+        match super::check_and_refresh_token(&conn, &token) {
+            Ok(true) => (),
+            Ok(false) => return Outcome::Failure((Status::Unauthorized, ())),
+            Err(err) => return Outcome::Failure((err.status(), ()))
         }
 
         use crate::schema::sessions;
-        let (username, course_name) = sessions::table.filter(sessions::token.eq(token))
-            .select((sessions::username, sessions::courseName))
-            .first::<(String, String)>(&crate::database_connection())
-            .expect("Database error");
+        let (username, course_name, scope) = match sessions::table.filter(sessions::token.eq(&token))
+            .select((sessions::username, sessions::courseName, sessions::scope))
+            .first::<(String, String, Option<String>)>(&*conn) {
+            Ok(session) => session,
+            Err(_) => return Outcome::Failure((Status::InternalServerError, ()))
+        };
+
+        // Scoped tokens (e.g. admin tokens) are only valid for their scope
+        if scope.is_some() {
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
 
-        Outcome::Success(User {
+        Outcome::Success(Session {
             name: username,
-            course: course_name
+            course: course_name,
+            token
         })
     }
 }
 
+/// A logged in user that is enrolled in the session's course.
+#[derive(Debug)]
+pub struct User {
+    pub name: String,
+    pub course: String
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let session = try_outcome!(req.guard::<Session>().await);
+        let conn = try_outcome!(req.guard::<DbConn>().await);
+
+        match crate::course::enrollments::is_enrolled(&conn, &session.name, &session.course) {
+            Ok(true) => Outcome::Success(User {
+                name: session.name,
+                course: session.course
+            }),
+            Ok(false) => Outcome::Failure((Status::Forbidden, ())),
+            Err(err) => Outcome::Failure((err.status(), ()))
+        }
+    }
+}
+
+/// A logged in user with at least instructor role in the session's course.
 #[derive(Debug)]
-pub struct AdminKey { }
+pub struct Instructor {
+    pub name: String,
+    pub course: String,
+    pub role: Role
+}
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for AdminKey {
+impl<'r> FromRequest<'r> for Instructor {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (user, role) = try_outcome!(user_with_role(req, Role::Instructor).await);
+
+        Outcome::Success(Instructor {
+            name: user.name,
+            course: user.course,
+            role
+        })
+    }
+}
+
+async fn user_with_role(req: &Request<'_>, required: Role) -> Outcome<(User, Role), ()> {
+    let user = try_outcome!(req.guard::<User>().await);
+    let conn = try_outcome!(req.guard::<DbConn>().await);
+
+    match super::roles::course_role(&conn, &user.name, &user.course) {
+        Ok(role) if role >= required => Outcome::Success((user, role)),
+        Ok(_) => Outcome::Failure((Status::Forbidden, ())),
+        Err(err) => Outcome::Failure((err.status(), ()))
+    }
+}
+
+/// An admin, authenticated either by an admin token of a user with admin flag
+/// or by the bootstrap key auth.admin_key (name "bootstrap").
+/// Every request with this guard is recorded in the adminAudit table.
+#[derive(Debug)]
+pub struct Admin {
+    pub name: String
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
@@ -55,17 +138,47 @@ impl<'r> FromRequest<'r> for AdminKey {
             token.unwrap()
         };
 
-        let admin_key = match SETTINGS.get::<String>("auth.admin_key") {
-            Ok(key) => key,
-            Err(_) => return Outcome::Failure((Status::Forbidden, ()))
+        let conn = try_outcome!(req.guard::<DbConn>().await);
+
+        let admin = match SETTINGS.get::<String>("auth.admin_key") {
+            Ok(admin_key) if admin_key == token => Ok(Some("bootstrap".to_string())),
+            _ => admin_token_user(&conn, &token)
+        };
+
+        let name = match admin {
+            Ok(Some(name)) => name,
+            Ok(None) => return Outcome::Failure((Status::Unauthorized, ())),
+            Err(err) => return Outcome::Failure((err.status(), ()))
         };
 
-        if admin_key == token {
-            return Outcome::Success(AdminKey { });
+        if let Err(err) = super::admin::audit(&conn, &name, req.method().as_str(), &req.uri().to_string()) {
+            return Outcome::Failure((err.status(), ()));
         }
 
-        Outcome::Failure((Status::Unauthorized, ()))
+        Outcome::Success(Admin { name })
+    }
+}
+
+/// Returns the owner of a valid admin token, if the owner (still) has the admin flag.
+fn admin_token_user(conn: &MysqlConnection, token: &str) -> Result<Option<String>, Error> {
+    if !super::check_and_refresh_token(conn, token)? {
+        return Ok(None);
     }
+
+    use crate::schema::sessions;
+    let (username, scope) = sessions::table.filter(sessions::token.eq(token))
+        .select((sessions::username, sessions::scope))
+        .first::<(String, Option<String>)>(conn)?;
+
+    if scope.as_deref() != Some(super::admin::ADMIN_SCOPE) {
+        return Ok(None);
+    }
+
+    if !super::admin::is_admin(conn, &username)? {
+        return Ok(None);
+    }
+
+    Ok(Some(username))
 }
 
 #[derive(Debug)]
@@ -1,104 +1,270 @@
 use percent_encoding::{utf8_percent_encode as perc_encode, AsciiSet, NON_ALPHANUMERIC};
 use hmac::{Hmac, Mac, NewMac};
 use crypto_hashes::sha2::Sha256;
+use crypto_hashes::sha1::Sha1;
 use rocket::response::Redirect;
 use rocket::serde::json::Json;
 use serde_json::Value;
 use rocket::http::Status;
-use rocket_dyn_templates::Template;
-use rocket::response::status;
 use diesel::prelude::*;
-use std::collections::{HashMap, BTreeMap};
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use std::collections::BTreeMap;
 use crate::tools::{epoch, data_to_string};
-use crate::SETTINGS;
+use crate::{SETTINGS, DbConn};
 use crate::auth::guards;
+use crate::auth::roles::Role;
+use crate::error::Error;
+
+/// Seconds a launch request is valid
+const MAX_AGE: i64 = 1800;
+/// Seconds a launch timestamp may lie in the future
+const MAX_CLOCK_SKEW: i64 = 300;
+/// Length of the consumerKey and nonce columns of ltiNonces
+const MAX_NONCE_LENGTH: usize = 128;
 
 #[post("/auth/login/lti", data = "<data>")]
-pub async fn auth_lti(data: rocket::Data<'_>) -> Result<Redirect, status::Custom<Template>> {
-    let empty_context: HashMap<String, String> = HashMap::new();
-    let data = data_to_string(data).await;
-    let lti_params: BTreeMap<String, String> = serde_urlencoded::from_str(&data).unwrap();
+pub async fn auth_lti(data: rocket::Data<'_>, conn: DbConn) -> Result<Redirect, Error> {
+    let invalid_request = Error::Template(Status::Unauthorized, "lti_invalid_request");
+    let data = data_to_string(data).await?;
+    let lti_params: BTreeMap<String, String> = match serde_urlencoded::from_str(&data) {
+        Ok(params) => params,
+        Err(_) => return Err(invalid_request)
+    };
     let lti_url: String = SETTINGS.get("auth.lti.url")
         .expect("auth.lti.url not found in settings");
-    let lti_secret: String = SETTINGS.get("auth.lti.secret")
-        .expect("auth.lti.secret not found in settings");
 
-    if !validate_lti(&lti_url, lti_params.clone(), &lti_secret) {
-        return Err(status::Custom(Status::Unauthorized, Template::render("lti_invalid_request", empty_context)));
+    let consumer = match lti_params.get("oauth_consumer_key") {
+        Some(key) => super::lti_consumers::launch_consumer(&conn, key)?,
+        None => None
+    };
+    let consumer = match consumer {
+        Some(consumer) => consumer,
+        None => return Err(invalid_request)
+    };
+
+    if !validate_lti(&lti_url, lti_params.clone(), &consumer.secret) {
+        return Err(invalid_request);
+    }
+
+    if !use_nonce(&conn, &lti_params)? {
+        return Err(invalid_request);
     }
 
-    let username = &lti_params["lis_person_sourcedid"];
+    let (sourcedid, display_name, context_id) = match (
+        lti_params.get("lis_person_sourcedid"),
+        lti_params.get("lis_person_name_given"),
+        lti_params.get("context_id")
+    ) {
+        (Some(sourcedid), Some(display_name), Some(context_id)) => (sourcedid, display_name, context_id),
+        _ => return Err(invalid_request)
+    };
+    let username = match super::lti_consumers::lti_username(&conn, &consumer.username_prefix, sourcedid)? {
+        Some(username) => username,
+        None => return Err(invalid_request)
+    };
+
+    let role = Role::from_lti_roles(lti_params.get("roles").map_or("", String::as_str).split(','));
+
+    let course = init_lti_user(&conn, &username, display_name, context_id, consumer.default_course.as_deref(), role)?;
+    super::lti_outcomes::store_launch_params(&conn, &username, &course, &lti_params)?;
+
+    lti_redirect(&conn, &username, &course)
+}
+
+#[put("/auth/ltiEnabled", data = "<data>")]
+pub fn put_lti_status(user: guards::User, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let new_status = data["ltiEnabled"].as_bool()
+        .ok_or(Status::BadRequest)?;
+
+    use crate::schema::users;
+    diesel::update(users::table.filter(users::username.eq(user.name)))
+        .set(users::ltiEnabled.eq(new_status))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
 
+/// Creates the user of a validated LTI launch (any LTI version) if necessary
+/// and applies the role from the LMS.
+/// Returns the course mapped to the LMS context or the default course, if
+/// there is no mapping.
+pub(crate) fn init_lti_user(conn: &MysqlConnection, username: &str, display_name: &str, context_id: &str, default_course: Option<&str>, role: Role) -> Result<String, Error> {
     super::try_init_user(
+        conn,
         username,
-        &lti_params["lis_person_name_given"],
+        display_name,
         &None
-    );
+    )?;
 
     use crate::schema::users;
     let lti_enabled = users::table.filter(users::username.eq(username))
         .select(users::ltiEnabled)
-        .first::<bool>(&crate::database_connection())
-        .expect("Database error");
+        .first::<bool>(conn)?;
 
     if !lti_enabled {
-        return Err(status::Custom(Status::Forbidden, Template::render("lti_disabled", empty_context)));
+        return Err(Error::Template(Status::Forbidden, "lti_disabled"));
     }
 
     use crate::schema::courseMapping;
-    let course = courseMapping::table.filter(courseMapping::studipId.eq(&lti_params["context_id"]))
+    let course = courseMapping::table.filter(courseMapping::studipId.eq(context_id))
         .select(courseMapping::courseName)
-        .first::<String>(&crate::database_connection())
-        .expect("Database error: Probably missing course mapping");
+        .first::<String>(conn)
+        .optional()?;
+
+    let course = match (course, default_course) {
+        (Some(course), _) => course,
+        (None, Some(course)) => course.to_string(),
+        (None, None) => return Err(Error::Template(Status::NotFound, "lti_unknown_course"))
+    };
 
-    let token = super::create_session(username, &course, &None);
+    if crate::course::management::is_archived(conn, &course)? {
+        return Err(Error::Template(Status::Forbidden, "lti_course_archived"));
+    }
+
+    super::roles::raise_role(conn, username, &course, role)?;
+
+    Ok(course)
+}
+
+/// Creates a session and redirects to the frontend.
+pub(crate) fn lti_redirect(conn: &MysqlConnection, username: &str, course: &str) -> Result<Redirect, Error> {
+    let token = super::create_session(conn, username, course, &None)?;
     let redirect_url = SETTINGS.get::<String>("auth.lti.redirect")
         .expect("auth.lti.redirect not found in settings");
 
     Ok(Redirect::to(format!("{}?token={}", redirect_url, token)))
 }
 
-#[put("/auth/ltiEnabled", data = "<data>")]
-pub fn put_lti_status(user: guards::User, data: Json<Value>) -> Result<Status, Status> {
-    let new_status = data["ltiEnabled"].as_bool()
-        .ok_or(Status::BadRequest)?;
+fn validate_lti(uri: &str, mut params: BTreeMap<String, String>, secret: &str) -> bool {
+    let studip_signature = match params.remove("oauth_signature") {
+        Some(signature) => signature,
+        None => return false
+    };
+    let timestamp = match params.get("oauth_timestamp").map(|t| t.parse::<i64>()) {
+        Some(Ok(timestamp)) => timestamp,
+        _ => return false
+    };
 
-    use crate::schema::users;
-    diesel::update(users::table.filter(users::username.eq(user.name)))
-        .set(users::ltiEnabled.eq(new_status))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+    let request_signature = oauth_signature(
+        SignatureMethod::HmacSha256,
+        &oauth_base_string("POST", uri, &params),
+        secret
+    );
 
-    Ok(Status::Ok)
+    request_signature == studip_signature
+        && timestamp + MAX_AGE > epoch()
+        && timestamp < epoch() + MAX_CLOCK_SKEW
 }
 
-fn validate_lti(uri: &str, mut params: BTreeMap<String, String>, secret: &str) -> bool {
-    let studip_signature = params.remove("oauth_signature").unwrap();
-    let timestamp = params["oauth_timestamp"].parse::<i64>().unwrap();
+/// Stores the oauth_nonce of a validated launch. Returns false if the nonce
+/// was already used, i.e. if the request is a replay. Nonces and consumer keys
+/// that don't fit into the ltiNonces table are refused as invalid request.
+fn use_nonce(conn: &MysqlConnection, params: &BTreeMap<String, String>) -> Result<bool, Error> {
+    let (consumer_key, nonce, timestamp) = match (
+        params.get("oauth_consumer_key"),
+        params.get("oauth_nonce"),
+        params.get("oauth_timestamp").and_then(|t| t.parse::<i64>().ok())
+    ) {
+        (Some(key), Some(nonce), Some(timestamp)) => (key, nonce, timestamp),
+        _ => return Ok(false)
+    };
+    if consumer_key.len() > MAX_NONCE_LENGTH || nonce.len() > MAX_NONCE_LENGTH {
+        return Err(Error::Template(Status::BadRequest, "lti_invalid_request"));
+    }
+
+    // Nonces only have to be stored as long as their timestamp is accepted
+    use crate::schema::ltiNonces;
+    diesel::delete(ltiNonces::table.filter(ltiNonces::expirationTime.lt(epoch())))
+        .execute(conn)?;
+
+    let result = diesel::insert_into(ltiNonces::table)
+        .values((
+            ltiNonces::consumerKey.eq(consumer_key),
+            ltiNonces::nonce.eq(nonce),
+            ltiNonces::expirationTime.eq(timestamp + MAX_AGE)
+        ))
+        .execute(conn);
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(false),
+        Err(err) => Err(err.into())
+    }
+}
 
-    // We want to percent encode all characters except 'A-Z', 'a-z',
-    // '0-9', '-', ',', '_', '~' (see RFC 3986)
-    const FRAGMENT: &AsciiSet = &NON_ALPHANUMERIC
-        .remove(b'-')
-        .remove(b'.')
-        .remove(b'_')
-        .remove(b'~');
+// We want to percent encode all characters except 'A-Z', 'a-z',
+// '0-9', '-', ',', '_', '~' (see RFC 3986)
+pub(crate) const FRAGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
+pub(crate) enum SignatureMethod {
+    HmacSha1,
+    HmacSha256
+}
+
+/// Signature base string of an OAuth 1.0 request (RFC 5849, section 3.4.1).
+/// `params` must not contain `oauth_signature`.
+pub(crate) fn oauth_base_string(http_method: &str, uri: &str, params: &BTreeMap<String, String>) -> String {
     let params_encoded = params.iter()
-        .map(|(k, v)| format!("{}={}", k, perc_encode(&v, FRAGMENT)))
+        .map(|(k, v)| format!("{}={}", perc_encode(k, FRAGMENT), perc_encode(v, FRAGMENT)))
         .collect::<Vec<_>>()
         .join("&");
 
-    let base_string = format!("POST&{}&{}",
-                              perc_encode(uri, FRAGMENT),
-                              perc_encode(&params_encoded, FRAGMENT)
-    );
+    format!("{}&{}&{}",
+            http_method,
+            perc_encode(uri, FRAGMENT),
+            perc_encode(&params_encoded, FRAGMENT)
+    )
+}
 
-    // Calculate the signature of our request.
+/// Base64 encoded HMAC signature of an OAuth base string. There is no token
+/// secret in LTI, so the key is just the encoded consumer secret and '&'.
+pub(crate) fn oauth_signature(method: SignatureMethod, base_string: &str, secret: &str) -> String {
     let secret = format!("{}&", perc_encode(secret, FRAGMENT));
-    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
-    mac.update(base_string.as_bytes());
-    let request_signature = base64::encode(mac.finalize().into_bytes());
 
-    request_signature == studip_signature && timestamp + 1800 > epoch()
-}
\ No newline at end of file
+    match method {
+        SignatureMethod::HmacSha1 => {
+            let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes()).unwrap();
+            mac.update(base_string.as_bytes());
+            base64::encode(mac.finalize().into_bytes())
+        },
+        SignatureMethod::HmacSha256 => {
+            let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+            mac.update(base_string.as_bytes());
+            base64::encode(mac.finalize().into_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_params(timestamp: i64) -> BTreeMap<String, String> {
+        let mut params = BTreeMap::new();
+        params.insert("oauth_consumer_key".to_string(), "studip".to_string());
+        params.insert("oauth_nonce".to_string(), "abc".to_string());
+        params.insert("oauth_timestamp".to_string(), timestamp.to_string());
+        params.insert("lis_person_sourcedid".to_string(), "bob smith".to_string());
+
+        let signature = oauth_signature(
+            SignatureMethod::HmacSha256,
+            &oauth_base_string("POST", "https://example.com/lti", &params),
+            "secret"
+        );
+        params.insert("oauth_signature".to_string(), signature);
+        params
+    }
+
+    #[test]
+    fn launch_timestamps() {
+        let url = "https://example.com/lti";
+        assert!(validate_lti(url, signed_params(epoch()), "secret"));
+        assert!(!validate_lti(url, signed_params(epoch()), "wrong secret"));
+        assert!(!validate_lti(url, signed_params(epoch() - MAX_AGE - 1), "secret"));
+        assert!(!validate_lti(url, signed_params(epoch() + MAX_CLOCK_SKEW + 60), "secret"));
+    }
+}
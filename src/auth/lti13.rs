@@ -0,0 +1,331 @@
+use rocket::form::Form;
+use rocket::response::Redirect;
+use rocket::http::Status;
+use diesel::prelude::*;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::time::Duration;
+use crate::schema::lti13Logins;
+use crate::tools::{epoch, random_string};
+use crate::{SETTINGS, DbConn};
+use crate::error::Error;
+use super::roles::Role;
+
+/// Seconds between login initiation and launch
+const LOGIN_TIMEOUT: i64 = 300;
+
+/// An LTI 1.3 platform (LMS) registered in the settings file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Platform {
+    pub issuer: String,
+    pub client_id: String,
+    /// OIDC authorization endpoint of the platform
+    pub auth_endpoint: String,
+    /// URL of the platform's public key set
+    pub jwks_url: String,
+    /// Accepted deployment ids; launches from other deployments are rejected
+    pub deployment_ids: Vec<String>,
+    /// Prepended to the user ids of the platform, so users of different LMS don't collide
+    #[serde(default)]
+    pub username_prefix: String
+}
+
+/// Parameters of the OIDC third-party login initiation.
+#[derive(Debug, FromForm)]
+pub struct LoginInitiation {
+    iss: String,
+    login_hint: String,
+    target_link_uri: String,
+    lti_message_hint: Option<String>,
+    client_id: Option<String>,
+    lti_deployment_id: Option<String>
+}
+
+#[derive(Debug, FromForm)]
+pub struct Launch {
+    id_token: String,
+    state: String
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub nonce: String,
+    pub name: Option<String>,
+    pub given_name: Option<String>,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/message_type")]
+    pub message_type: String,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/version")]
+    pub version: String,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/deployment_id")]
+    pub deployment_id: String,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/context")]
+    pub context: Option<ContextClaim>,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/lis", default)]
+    pub lis: Option<LisClaim>,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/roles", default)]
+    pub roles: Vec<String>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContextClaim {
+    pub id: String
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LisClaim {
+    pub person_sourcedid: Option<String>
+}
+
+#[get("/auth/lti13/login?<params..>")]
+pub fn get_lti13_login(params: LoginInitiation, conn: DbConn) -> Result<Redirect, Error> {
+    login_initiation(&conn, params)
+}
+
+#[post("/auth/lti13/login", data = "<params>")]
+pub fn post_lti13_login(params: Form<LoginInitiation>, conn: DbConn) -> Result<Redirect, Error> {
+    login_initiation(&conn, params.into_inner())
+}
+
+#[post("/auth/lti13/launch", data = "<launch>")]
+pub async fn auth_lti13(launch: Form<Launch>, conn: DbConn) -> Result<Redirect, Error> {
+    let invalid_request = Error::Template(Status::Unauthorized, "lti_invalid_request");
+
+    // States can only be used once
+    let login = lti13Logins::table.filter(lti13Logins::state.eq(&launch.state))
+        .select((lti13Logins::nonce, lti13Logins::issuer, lti13Logins::clientId, lti13Logins::expirationTime))
+        .first::<(String, String, String, i64)>(&*conn)
+        .optional()?;
+    diesel::delete(lti13Logins::table.filter(lti13Logins::state.eq(&launch.state)))
+        .execute(&*conn)?;
+
+    let (nonce, issuer, client_id) = match login {
+        Some((nonce, issuer, client_id, expiration_time)) if expiration_time >= epoch() => (nonce, issuer, client_id),
+        _ => return Err(invalid_request)
+    };
+
+    let platform = find_platform(&issuer, Some(&client_id))
+        .ok_or(invalid_request)?;
+
+    let jwks_timeout = SETTINGS.get::<u64>("auth.lti13.jwks_timeout")
+        .expect("auth.lti13.jwks_timeout missing in settings");
+    let jwks = reqwest::Client::builder()
+        .timeout(Duration::from_secs(jwks_timeout))
+        .build()
+        .map_err(|err| Error::Lms(err.to_string()))?
+        .get(&platform.jwks_url)
+        .send()
+        .await
+        .map_err(|err| Error::Lms(err.to_string()))?
+        .json::<Value>()
+        .await
+        .map_err(|err| Error::Lms(err.to_string()))?;
+
+    let claims = validate_id_token(&launch.id_token, &platform, &jwks, &nonce)
+        .map_err(|_| Error::Template(Status::Unauthorized, "lti_invalid_request"))?;
+
+    let context_id = match &claims.context {
+        Some(context) => &context.id,
+        None => return Err(Error::Template(Status::Unauthorized, "lti_invalid_request"))
+    };
+
+    // Prefer the LIS id, so users keep their account when the LMS switches from LTI 1.1
+    let user_id = claims.lis.as_ref()
+        .and_then(|lis| lis.person_sourcedid.as_deref())
+        .unwrap_or(&claims.sub);
+    let username = super::lti_consumers::lti_username(&conn, &platform.username_prefix, user_id)?
+        .ok_or(Error::Template(Status::Unauthorized, "lti_invalid_request"))?;
+    let display_name = claims.given_name.as_ref()
+        .or(claims.name.as_ref())
+        .unwrap_or(&username);
+
+    let course = super::lti::init_lti_user(
+        &conn,
+        &username,
+        display_name,
+        context_id,
+        None,
+        Role::from_lti_roles(claims.roles.iter().map(String::as_str))
+    )?;
+    super::lti::lti_redirect(&conn, &username, &course)
+}
+
+/// Answers the login initiation with a redirect to the authorization endpoint
+/// of the platform, which then POSTs the id token to the launch route.
+fn login_initiation(conn: &MysqlConnection, params: LoginInitiation) -> Result<Redirect, Error> {
+    let platform = find_platform(&params.iss, params.client_id.as_deref())
+        .ok_or(Error::Template(Status::Unauthorized, "lti_invalid_request"))?;
+
+    if let Some(deployment_id) = &params.lti_deployment_id {
+        if !platform.deployment_ids.contains(deployment_id) {
+            return Err(Error::Template(Status::Unauthorized, "lti_invalid_request"));
+        }
+    }
+
+    let launch_url: String = SETTINGS.get("auth.lti13.url")
+        .expect("auth.lti13.url not found in settings");
+    if params.target_link_uri != launch_url {
+        return Err(Error::Template(Status::Unauthorized, "lti_invalid_request"));
+    }
+
+    let state = random_string(32);
+    let nonce = random_string(32);
+
+    diesel::delete(lti13Logins::table.filter(lti13Logins::expirationTime.lt(epoch())))
+        .execute(conn)?;
+    diesel::insert_into(lti13Logins::table)
+        .values((
+            lti13Logins::state.eq(&state),
+            lti13Logins::nonce.eq(&nonce),
+            lti13Logins::issuer.eq(&platform.issuer),
+            lti13Logins::clientId.eq(&platform.client_id),
+            lti13Logins::expirationTime.eq(epoch() + LOGIN_TIMEOUT)
+        ))
+        .execute(conn)?;
+
+    let mut query = vec![
+        ("scope", "openid"),
+        ("response_type", "id_token"),
+        ("response_mode", "form_post"),
+        ("prompt", "none"),
+        ("client_id", &platform.client_id),
+        ("redirect_uri", &launch_url),
+        ("login_hint", &params.login_hint),
+        ("state", &state),
+        ("nonce", &nonce)
+    ];
+    if let Some(hint) = &params.lti_message_hint {
+        query.push(("lti_message_hint", hint));
+    }
+
+    let query = serde_urlencoded::to_string(query)
+        .or(Err(Status::InternalServerError))?;
+    Ok(Redirect::to(format!("{}?{}", platform.auth_endpoint, query)))
+}
+
+/// Platforms registered in the settings file
+pub fn platforms() -> Vec<Platform> {
+    SETTINGS.get::<Vec<Platform>>("auth.lti13.platforms")
+        .unwrap_or_default()
+}
+
+fn find_platform(issuer: &str, client_id: Option<&str>) -> Option<Platform> {
+    platforms()
+        .into_iter()
+        .find(|platform| {
+            platform.issuer == issuer && client_id.is_none_or(|id| platform.client_id == id)
+        })
+}
+
+/// Validates signature, issuer, audience and expiration of an id token and
+/// checks the LTI specific claims. `jwks` is the key set of the platform.
+pub fn validate_id_token(id_token: &str, platform: &Platform, jwks: &Value, nonce: &str) -> Result<Claims, Error> {
+    let invalid = || Error::Status(Status::Unauthorized);
+
+    let header = decode_header(id_token).map_err(|_| invalid())?;
+    let key = jwks["keys"].as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .filter(|key| key["kty"] == "RSA")
+        .find(|key| header.kid.is_none() || key["kid"].as_str() == header.kid.as_deref())
+        .ok_or_else(invalid)?;
+    let key = DecodingKey::from_rsa_components(
+        key["n"].as_str().ok_or_else(invalid)?,
+        key["e"].as_str().ok_or_else(invalid)?
+    );
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.iss = Some(platform.issuer.clone());
+    validation.set_audience(&[&platform.client_id]);
+
+    let claims = decode::<Claims>(id_token, &key, &validation)
+        .map_err(|_| invalid())?
+        .claims;
+
+    if claims.nonce != nonce
+        || claims.version != "1.3.0"
+        || claims.message_type != "LtiResourceLinkRequest"
+        || !platform.deployment_ids.contains(&claims.deployment_id) {
+        return Err(invalid());
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    // Generated for these tests only
+    const PRIVATE_KEY: &str = include_str!("../../test_data/lti13_platform_key.pem");
+    const MODULUS: &str = "tG1wKCsRgCZXs2t5pHHYb814DtFp4eA0hHw6yuJ0GH540LH3j6lNczSYx9Ed4e6hgu8bzVP0NJCNyEfcXP67v_yk-plkVYPIxhQiITd22ONOF2u6me7HIMH3jKtyD9q274zLJwl4C3t7dADZ9f-gAeWDOjCfiq_4mpmgewOBRCFRi2fyPRrYSW-oYKle95pgg4AmYXKYdrVMqBBg2ppDt25nRTnixv_ntunXSJK0UIePgj7hamwxn_lKYuEl0C0EUBwMXezZoenJ_vn1jFcOyv2n6A2GvS_aCUhlUznJ1nVPg8Ho4c1UXsKKcv0Cmje-gWNLPEicbevlOIGS6KLBZQ";
+
+    fn platform() -> Platform {
+        Platform {
+            issuer: "https://lms.example.com".to_string(),
+            client_id: "smartbeans".to_string(),
+            auth_endpoint: "https://lms.example.com/auth".to_string(),
+            jwks_url: "https://lms.example.com/jwks".to_string(),
+            deployment_ids: vec!["1".to_string()],
+            username_prefix: String::new()
+        }
+    }
+
+    fn jwks() -> Value {
+        json!({ "keys": [{ "kty": "RSA", "kid": "key1", "alg": "RS256", "n": MODULUS, "e": "AQAB" }] })
+    }
+
+    fn id_token(patch: Value) -> String {
+        let mut claims = json!({
+            "iss": "https://lms.example.com",
+            "aud": ["smartbeans"],
+            "sub": "a6d5c443-1f51-4783-ba1a-7686ffe3b54a",
+            "exp": epoch() + 60,
+            "iat": epoch(),
+            "nonce": "nonce",
+            "given_name": "Bob",
+            "https://purl.imsglobal.org/spec/lti/claim/message_type": "LtiResourceLinkRequest",
+            "https://purl.imsglobal.org/spec/lti/claim/version": "1.3.0",
+            "https://purl.imsglobal.org/spec/lti/claim/deployment_id": "1",
+            "https://purl.imsglobal.org/spec/lti/claim/context": { "id": "course-1" },
+            "https://purl.imsglobal.org/spec/lti/claim/lis": { "person_sourcedid": "bob" },
+            "https://purl.imsglobal.org/spec/lti/claim/roles": ["http://purl.imsglobal.org/vocab/lis/v2/membership#Learner"]
+        });
+        for (key, value) in patch.as_object().unwrap() {
+            claims[key] = value.clone();
+        }
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("key1".to_string());
+        encode(&header, &claims, &EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn valid_launch() {
+        let claims = validate_id_token(&id_token(json!({})), &platform(), &jwks(), "nonce").unwrap();
+        assert_eq!(claims.lis.unwrap().person_sourcedid.unwrap(), "bob");
+        assert_eq!(claims.context.unwrap().id, "course-1");
+    }
+
+    #[test]
+    fn invalid_launches() {
+        let platform = platform();
+        let jwks = jwks();
+        let invalid_claims = vec![
+            json!({ "iss": "https://evil.example.com" }),
+            json!({ "aud": "someone-else" }),
+            json!({ "exp": epoch() - 3600 }),
+            json!({ "nonce": "replayed" }),
+            json!({ "https://purl.imsglobal.org/spec/lti/claim/deployment_id": "2" }),
+            json!({ "https://purl.imsglobal.org/spec/lti/claim/version": "1.1" })
+        ];
+
+        for claims in invalid_claims {
+            assert!(validate_id_token(&id_token(claims.clone()), &platform, &jwks, "nonce").is_err(), "{}", claims);
+        }
+
+        let other_key = json!({ "keys": [{ "kty": "RSA", "kid": "key1", "n": MODULUS.replace('t', "u"), "e": "AQAB" }] });
+        assert!(validate_id_token(&id_token(json!({})), &platform, &other_key, "nonce").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use serde_json::Value;
+use diesel::prelude::*;
+use crate::schema::ltiConsumers;
+use crate::auth::guards;
+use crate::{SETTINGS, DbConn};
+use crate::error::Error;
+
+/// An LMS instance that may launch SmartBeans via LTI 1.1.
+#[derive(Debug, Serialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct Consumer {
+    pub consumer_key: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub enabled: bool,
+    /// Course for launches from LMS contexts without course mapping
+    pub default_course: Option<String>,
+    /// Prepended to lis_person_sourcedid, so users of different LMS don't collide
+    pub username_prefix: String
+}
+
+#[get("/admin/ltiConsumers")]
+pub fn route_get_lti_consumers(_admin: guards::Admin, conn: DbConn) -> Result<Json<Vec<Consumer>>, Error> {
+    Ok(Json(ltiConsumers::table.load::<Consumer>(&*conn)?))
+}
+
+#[post("/admin/ltiConsumers", data = "<data>")]
+pub fn route_post_lti_consumer(_admin: guards::Admin, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let consumer_key = data["consumerKey"].as_str()
+        .ok_or(Status::BadRequest)?;
+    let secret = data["secret"].as_str()
+        .ok_or(Status::BadRequest)?;
+
+    if find_consumer(&conn, consumer_key)?.is_some() {
+        return Err(Status::Conflict.into());
+    }
+
+    diesel::insert_into(ltiConsumers::table)
+        .values((
+            ltiConsumers::consumerKey.eq(consumer_key),
+            ltiConsumers::secret.eq(secret),
+            ltiConsumers::enabled.eq(data["enabled"].as_bool().unwrap_or(true)),
+            ltiConsumers::defaultCourse.eq(data["defaultCourse"].as_str()),
+            ltiConsumers::usernamePrefix.eq(data["usernamePrefix"].as_str().unwrap_or(""))
+        ))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+/// Updates all fields given in the body; `"defaultCourse": null` removes the default course.
+#[patch("/admin/ltiConsumers/<consumer_key>", data = "<data>")]
+pub fn route_patch_lti_consumer(_admin: guards::Admin, consumer_key: String, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let consumer = find_consumer(&conn, &consumer_key)?
+        .ok_or(Status::NotFound)?;
+
+    let default_course = match data.get("defaultCourse") {
+        Some(Value::Null) => None,
+        Some(course) => Some(course.as_str().ok_or(Status::BadRequest)?.to_string()),
+        None => consumer.default_course
+    };
+
+    diesel::update(ltiConsumers::table.filter(ltiConsumers::consumerKey.eq(&consumer_key)))
+        .set((
+            ltiConsumers::secret.eq(data["secret"].as_str().unwrap_or(&consumer.secret)),
+            ltiConsumers::enabled.eq(data["enabled"].as_bool().unwrap_or(consumer.enabled)),
+            ltiConsumers::defaultCourse.eq(default_course),
+            ltiConsumers::usernamePrefix.eq(data["usernamePrefix"].as_str().unwrap_or(&consumer.username_prefix))
+        ))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+#[delete("/admin/ltiConsumers/<consumer_key>")]
+pub fn route_delete_lti_consumer(_admin: guards::Admin, consumer_key: String, conn: DbConn) -> Result<Status, Error> {
+    let deleted = diesel::delete(ltiConsumers::table.filter(ltiConsumers::consumerKey.eq(&consumer_key)))
+        .execute(&*conn)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound.into());
+    }
+
+    Ok(Status::Ok)
+}
+
+pub fn find_consumer(conn: &MysqlConnection, consumer_key: &str) -> Result<Option<Consumer>, Error> {
+    Ok(ltiConsumers::table.filter(ltiConsumers::consumerKey.eq(consumer_key))
+        .first::<Consumer>(conn)
+        .optional()?)
+}
+
+/// Username of an LMS user: the user id with the username prefix of its
+/// consumer or platform. Returns None if the username would fall into the
+/// namespace of another consumer or platform with a longer prefix, e.g. the id
+/// "unibob" of a consumer without prefix if another consumer has the prefix "uni".
+pub(crate) fn lti_username(conn: &MysqlConnection, prefix: &str, id: &str) -> Result<Option<String>, Error> {
+    let mut prefixes = ltiConsumers::table.select(ltiConsumers::usernamePrefix)
+        .load::<String>(conn)?;
+    prefixes.extend(super::lti13::platforms().into_iter().map(|platform| platform.username_prefix));
+
+    Ok(namespaced_username(prefix, id, &prefixes))
+}
+
+fn namespaced_username(prefix: &str, id: &str, prefixes: &[String]) -> Option<String> {
+    let username = format!("{}{}", prefix, id);
+    let foreign = prefixes.iter()
+        .any(|other| other.len() > prefix.len() && username.starts_with(other.as_str()));
+
+    Some(username).filter(|_| !foreign)
+}
+
+/// Returns the consumer for a launch. Consumer keys without entry in the
+/// ltiConsumers table fall back to auth.lti.secret from the settings, if set.
+/// Returns None for unknown and disabled consumers.
+pub fn launch_consumer(conn: &MysqlConnection, consumer_key: &str) -> Result<Option<Consumer>, Error> {
+    if let Some(consumer) = find_consumer(conn, consumer_key)? {
+        return Ok(Some(consumer).filter(|consumer| consumer.enabled));
+    }
+
+    Ok(SETTINGS.get::<String>("auth.lti.secret").ok().map(|secret| {
+        Consumer {
+            consumer_key: consumer_key.to_string(),
+            secret,
+            enabled: true,
+            default_course: None,
+            username_prefix: String::new()
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn username_namespaces() {
+        let prefixes = vec!["".to_string(), "uni".to_string(), "unig".to_string()];
+
+        assert_eq!(namespaced_username("", "bob", &prefixes), Some("bob".to_string()));
+        assert_eq!(namespaced_username("", "unibob", &prefixes), None);
+        assert_eq!(namespaced_username("uni", "bob", &prefixes), Some("unibob".to_string()));
+        assert_eq!(namespaced_username("uni", "gbob", &prefixes), None);
+        assert_eq!(namespaced_username("unig", "bob", &prefixes), Some("unigbob".to_string()));
+    }
+}
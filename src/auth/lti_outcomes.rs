@@ -0,0 +1,234 @@
+use diesel::prelude::*;
+use crypto_hashes::sha1::{Sha1, Digest};
+use percent_encoding::utf8_percent_encode as perc_encode;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use std::collections::BTreeMap;
+use super::lti::{oauth_base_string, oauth_signature, SignatureMethod, FRAGMENT};
+use crate::schema::ltiOutcomes;
+use crate::tools::{epoch, random_string};
+use crate::error::Error;
+use crate::{DbConn, DbPool};
+
+#[derive(Debug, Queryable)]
+struct Outcome {
+    username: String,
+    course: String,
+    consumer_key: String,
+    service_url: String,
+    sourcedid: String,
+    last_grade: Option<f32>
+}
+
+/// Stores the LTI 1.1 Outcomes parameters of a launch, if the LMS sent them.
+/// The LMS only accepts grades for the sourcedid of the latest launch.
+pub fn store_launch_params(conn: &MysqlConnection, username: &str, course: &str, params: &BTreeMap<String, String>) -> Result<(), Error> {
+    let (service_url, sourcedid, consumer_key) = match (
+        params.get("lis_outcome_service_url"),
+        params.get("lis_result_sourcedid"),
+        params.get("oauth_consumer_key")
+    ) {
+        (Some(url), Some(sourcedid), Some(key)) => (url, sourcedid, key),
+        _ => return Ok(())
+    };
+
+    diesel::replace_into(ltiOutcomes::table)
+        .values((
+            ltiOutcomes::username.eq(username),
+            ltiOutcomes::course.eq(course),
+            ltiOutcomes::consumerKey.eq(consumer_key),
+            ltiOutcomes::serviceUrl.eq(service_url),
+            ltiOutcomes::sourcedid.eq(sourcedid),
+            ltiOutcomes::lastGrade.eq(None::<f32>)
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Sends the course grade of a user to the LMS in a background task.
+/// Failures are only logged, they should never affect the submission itself.
+pub fn report_grade_in_background(pool: &DbPool, username: &str, course: &str) {
+    let (pool, username, course) = (pool.clone(), username.to_string(), course.to_string());
+
+    rocket::tokio::spawn(async move {
+        if let Err(err) = report_grade(&pool, &username, &course).await {
+            error_!("LTI grade passback for {} in {} failed: {}", username, course, err);
+        }
+    });
+}
+
+/// Sends the course grade of a user to the LMS, if the user launched the course
+/// via LTI with outcomes enabled and the grade changed since the last report.
+pub async fn report_grade(pool: &DbPool, username: &str, course: &str) -> Result<(), Error> {
+    let (outcome, consumer, grade) = {
+        let conn = DbConn::get(pool).await?;
+        let outcome = ltiOutcomes::table.filter(ltiOutcomes::username.eq(username))
+            .filter(ltiOutcomes::course.eq(course))
+            .first::<Outcome>(&*conn)
+            .optional()?;
+        let outcome = match outcome {
+            Some(outcome) => outcome,
+            None => return Ok(())
+        };
+
+        // Consumers that were disabled or deleted don't get grades anymore
+        let consumer = match super::lti_consumers::launch_consumer(&conn, &outcome.consumer_key)? {
+            Some(consumer) => consumer,
+            None => return Ok(())
+        };
+
+        let grade = crate::course::grading::course_grade(&conn, username, course)?;
+        (outcome, consumer, grade)
+    };
+
+    if outcome.last_grade == Some(grade as f32) {
+        return Ok(());
+    }
+
+    replace_result(&outcome.service_url, &consumer.consumer_key, &consumer.secret, &outcome.sourcedid, grade).await?;
+
+    diesel::update(ltiOutcomes::table.filter(ltiOutcomes::username.eq(&outcome.username)))
+        .filter(ltiOutcomes::course.eq(&outcome.course))
+        .set(ltiOutcomes::lastGrade.eq(grade as f32))
+        .execute(&*DbConn::get(pool).await?)?;
+
+    Ok(())
+}
+
+/// Sends a signed replaceResult request (LTI 1.1 Basic Outcomes) to the LMS.
+async fn replace_result(service_url: &str, consumer_key: &str, secret: &str, sourcedid: &str, grade: f64) -> Result<(), Error> {
+    let body = replace_result_body(&random_string(32), sourcedid, grade);
+
+    let url = reqwest::Url::parse(service_url)
+        .map_err(|err| Error::Lms(format!("Invalid outcome service URL: {}", err)))?;
+
+    let mut oauth_params = BTreeMap::new();
+    oauth_params.insert("oauth_body_hash".to_string(), base64::encode(Sha1::digest(body.as_bytes())));
+    oauth_params.insert("oauth_consumer_key".to_string(), consumer_key.to_string());
+    oauth_params.insert("oauth_nonce".to_string(), random_string(32));
+    oauth_params.insert("oauth_signature_method".to_string(), "HMAC-SHA1".to_string());
+    oauth_params.insert("oauth_timestamp".to_string(), epoch().to_string());
+    oauth_params.insert("oauth_version".to_string(), "1.0".to_string());
+
+    // Query parameters of the service URL are part of the signature, but not of the base URI
+    let mut params = oauth_params.clone();
+    params.extend(url.query_pairs().map(|(k, v)| (k.to_string(), v.to_string())));
+    let mut base_url = url.clone();
+    base_url.set_query(None);
+    base_url.set_fragment(None);
+
+    let signature = oauth_signature(
+        SignatureMethod::HmacSha1,
+        &oauth_base_string("POST", base_url.as_str(), &params),
+        secret
+    );
+    oauth_params.insert("oauth_signature".to_string(), signature);
+
+    let authorization = oauth_params.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, perc_encode(v, FRAGMENT)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .header(AUTHORIZATION, format!("OAuth {}", authorization))
+        .header(CONTENT_TYPE, "application/xml")
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| Error::Lms(err.to_string()))?;
+
+    let status = response.status();
+    let response = response.text()
+        .await
+        .map_err(|err| Error::Lms(err.to_string()))?;
+
+    if !status.is_success() || !response.contains("<imsx_codeMajor>success</imsx_codeMajor>") {
+        return Err(Error::Lms(format!("replaceResult rejected ({}): {}", status, response)));
+    }
+
+    Ok(())
+}
+
+fn replace_result_body(message_id: &str, sourcedid: &str, grade: f64) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<imsx_POXEnvelopeRequest xmlns="http://www.imsglobal.org/services/ltiv1p1/xsd/imsoms_v1p0">
+  <imsx_POXHeader>
+    <imsx_POXRequestHeaderInfo>
+      <imsx_version>V1.0</imsx_version>
+      <imsx_messageIdentifier>{}</imsx_messageIdentifier>
+    </imsx_POXRequestHeaderInfo>
+  </imsx_POXHeader>
+  <imsx_POXBody>
+    <replaceResultRequest>
+      <resultRecord>
+        <sourcedGUID>
+          <sourcedId>{}</sourcedId>
+        </sourcedGUID>
+        <result>
+          <resultScore>
+            <language>en</language>
+            <textString>{}</textString>
+          </resultScore>
+        </result>
+      </resultRecord>
+    </replaceResultRequest>
+  </imsx_POXBody>
+</imsx_POXEnvelopeRequest>"#, message_id, xml_escape(sourcedid), grade.clamp(0.0, 1.0))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::mock_http_server;
+
+    const SUCCESS: &str = "<imsx_POXEnvelopeResponse><imsx_POXHeader><imsx_POXResponseHeaderInfo>\
+        <imsx_statusInfo><imsx_codeMajor>success</imsx_codeMajor></imsx_statusInfo>\
+        </imsx_POXResponseHeaderInfo></imsx_POXHeader></imsx_POXEnvelopeResponse>";
+
+    #[rocket::async_test]
+    async fn signed_replace_result() {
+        let (url, requests) = mock_http_server(|_| (200, SUCCESS.to_string())).await;
+        let service_url = format!("{}/outcomes?course=42", url);
+
+        replace_result(&service_url, "smartbeans", "secret", "abc&123", 0.75).await.unwrap();
+
+        let request = requests.lock().unwrap().pop().unwrap();
+        assert!(request.body.contains("<sourcedId>abc&amp;123</sourcedId>"));
+        assert!(request.body.contains("<textString>0.75</textString>"));
+
+        // Verify the OAuth signature like the LMS would
+        let mut params = request.header("authorization").unwrap()
+            .trim_start_matches("OAuth ")
+            .split(", ")
+            .map(|param| {
+                let (k, v) = param.split_once('=').unwrap();
+                let v = percent_encoding::percent_decode_str(v.trim_matches('"')).decode_utf8().unwrap();
+                (k.to_string(), v.to_string())
+            })
+            .collect::<BTreeMap<_, _>>();
+        let signature = params.remove("oauth_signature").unwrap();
+        assert_eq!(params["oauth_body_hash"], base64::encode(Sha1::digest(request.body.as_bytes())));
+
+        params.insert("course".to_string(), "42".to_string());
+        let base_string = oauth_base_string("POST", &format!("{}/outcomes", url), &params);
+        assert_eq!(signature, oauth_signature(SignatureMethod::HmacSha1, &base_string, "secret"));
+    }
+
+    #[rocket::async_test]
+    async fn rejected_replace_result() {
+        let (url, _) = mock_http_server(|_| {
+            (200, "<imsx_codeMajor>failure</imsx_codeMajor>".to_string())
+        }).await;
+
+        assert!(replace_result(&url, "smartbeans", "secret", "abc", 1.0).await.is_err());
+    }
+}
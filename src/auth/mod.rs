@@ -1,45 +1,89 @@
-use rand::{thread_rng, Rng, distributions::Alphanumeric};
 use diesel::prelude::*;
 use std::time::{SystemTime, Duration, UNIX_EPOCH};
-use crate::SETTINGS;
+use crate::{SETTINGS, DbConn};
+use crate::error::Error;
 use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde_json::Value;
 
 pub mod password;
 pub mod lti;
+pub mod lti_outcomes;
+pub mod lti13;
+pub mod lti_consumers;
+pub mod roles;
 pub mod guards;
 pub mod api_token;
+pub mod admin;
 
 #[post("/auth/login/debug/<username>/<course>")]
-pub fn auth_debug(username: String, course: String, _key: guards::AdminKey) -> Result<String, Status> {
+pub fn auth_debug(username: String, course: String, _admin: guards::Admin, conn: DbConn) -> Result<String, Error> {
     use crate::schema::users;
     users::table.filter(users::username.eq(&username))
         .select(users::username)
-        .first::<String>(&crate::database_connection())
-        .or(Err(Status::NotFound))?;
+        .first::<String>(&*conn)?;
 
-    if crate::course::name_to_title(&course).is_none() {
-        return Err(Status::NotFound);
+    if !crate::course::enrollments::is_enrolled(&conn, &username, &course)? {
+        return Err(Status::NotFound.into());
     }
 
-    Ok(create_session(&username, &course, &None))
+    create_session(&conn, &username, &course, &None)
+}
+
+/// Switches the course of the current session. The user has to be enrolled in the course.
+#[put("/auth/activeCourse/<course>")]
+pub fn put_active_course(session: guards::Session, course: String, conn: DbConn) -> Result<Status, Error> {
+    check_course_switch(&conn, &session.name, &course)?;
+
+    use crate::schema::sessions;
+    diesel::update(sessions::table.filter(sessions::token.eq(&session.token)))
+        .set(sessions::courseName.eq(&course))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+/// Issues an additional session for another course, e.g. to have two courses
+/// open in different browser tabs. The current session stays valid.
+#[post("/auth/activeCourse/<course>")]
+pub fn post_active_course(session: guards::Session, course: String, conn: DbConn) -> Result<Json<Value>, Error> {
+    check_course_switch(&conn, &session.name, &course)?;
+
+    Ok(Json(json!({
+        "token": create_session(&conn, &session.name, &course, &None)?
+    })))
 }
 
 #[delete("/auth/logout/<token>")]
-pub fn logout(token: String) -> Status {
+pub fn logout(token: String, conn: DbConn) -> Result<Status, Error> {
     use crate::schema::sessions;
     diesel::delete(sessions::table.filter(sessions::token.eq(&token)))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+fn check_course_switch(conn: &MysqlConnection, username: &str, course: &str) -> Result<(), Error> {
+    if crate::course::name_to_title(conn, course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+
+    if !crate::course::enrollments::is_enrolled(conn, username, course)?
+        || crate::course::management::is_archived(conn, course)? {
+        return Err(Status::Forbidden.into());
+    }
 
-    Status::Ok
+    Ok(())
 }
 
-fn create_session(user: &str, course: &str, token_name: &Option<String>) -> String {
-    let token: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(32)
-        .map(char::from)
-        .collect();
+fn create_session(conn: &MysqlConnection, user: &str, course: &str, token_name: &Option<String>) -> Result<String, Error> {
+    create_scoped_session(conn, user, course, token_name, None)
+}
+
+/// Creates a session that is only valid for the given scope (see admin::ADMIN_SCOPE).
+/// Unscoped sessions are regular user sessions.
+fn create_scoped_session(conn: &MysqlConnection, user: &str, course: &str, token_name: &Option<String>, scope: Option<&str>) -> Result<String, Error> {
+    let token = crate::tools::random_string(32);
 
     use crate::schema::sessions;
     diesel::insert_into(sessions::table)
@@ -48,38 +92,37 @@ fn create_session(user: &str, course: &str, token_name: &Option<String>) -> Stri
             sessions::username.eq(user),
             sessions::courseName.eq(course),
             sessions::expirationTime.eq(expiration_time() as i64),
-            sessions::tokenName.eq(token_name)
+            sessions::tokenName.eq(token_name),
+            sessions::scope.eq(scope)
         ))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(conn)?;
 
-    token
+    Ok(token)
 }
 
-fn check_and_refresh_token(token: &str) -> bool {
+fn check_and_refresh_token(conn: &MysqlConnection, token: &str) -> Result<bool, Error> {
     use crate::schema::sessions;
     let result = sessions::table.filter(sessions::token.eq(token))
         .select((sessions::expirationTime, sessions::tokenName))
-        .first::<(i64, Option<String>)>(&crate::database_connection());
-
-    if result.is_err() {
-        return false;
-    }
+        .first::<(i64, Option<String>)>(conn)
+        .optional()?;
 
-    let (current_expiration_time, token_name) = result.unwrap();
+    let (current_expiration_time, token_name) = match result {
+        Some(session) => session,
+        None => return Ok(false)
+    };
 
     // Return false if the expiration time is in the past and the token is not a permanent API token
     if token_name.is_none() && current_expiration_time < crate::tools::epoch() {
-        return false;
+        return Ok(false);
     }
 
     // Otherwise refresh the expiration time of the token
     diesel::update(sessions::table.filter(sessions::token.eq(token)))
         .set(sessions::expirationTime.eq(expiration_time() as i64))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(conn)?;
 
-    true
+    Ok(true)
 }
 
 fn expiration_time() -> u64 {
@@ -93,7 +136,7 @@ fn expiration_time() -> u64 {
 
 /// Initializes a new user. Returns false if the username already exists.
 /// Use password = None if the user logged in via LTI.
-fn try_init_user(username: &str, display_name: &str, password_hash: &Option<String>) -> bool {
+fn try_init_user(conn: &MysqlConnection, username: &str, display_name: &str, password_hash: &Option<String>) -> Result<bool, Error> {
     let char_data = json!({
         "bodyColor": null,
         "hatId": null,
@@ -105,11 +148,12 @@ fn try_init_user(username: &str, display_name: &str, password_hash: &Option<Stri
     use crate::schema::users;
     let user_exists = users::table.filter(users::username.eq(username))
         .select(users::username)
-        .first::<String>(&crate::database_connection())
-        .is_ok();
+        .first::<String>(conn)
+        .optional()?
+        .is_some();
 
     if user_exists {
-        return false;
+        return Ok(false);
     }
 
     diesel::insert_into(users::table)
@@ -118,10 +162,9 @@ fn try_init_user(username: &str, display_name: &str, password_hash: &Option<Stri
             users::displayName.eq(display_name),
             users::password.eq(password_hash),
             users::ltiEnabled.eq(password_hash.is_none()),
-            users::charData.eq(serde_json::to_string(&char_data).unwrap())
+            users::charData.eq(serde_json::to_string(&char_data)?)
         ))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(conn)?;
 
-    true
+    Ok(true)
 }
\ No newline at end of file
@@ -1,12 +1,15 @@
 use super::guards;
+use super::roles::Role;
 use rocket::serde::json::Json;
 use serde_json::Value;
 use rocket::http::Status;
 use rand::Rng;
 use diesel::prelude::*;
+use crate::DbConn;
+use crate::error::Error;
 
 #[post("/auth/register", data = "<data>")]
-pub fn post_register(_key: guards::RegistrationKey, data: Json<Value>) -> Result<Status, Status> {
+pub fn post_register(_key: guards::RegistrationKey, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
     let username = data["username"].as_str()
         .ok_or(Status::BadRequest)?;
     let password = data["password"].as_str()
@@ -16,16 +19,16 @@ pub fn post_register(_key: guards::RegistrationKey, data: Json<Value>) -> Result
 
     let password_hash = password_hash(password);
 
-    if super::try_init_user(username, display_name, &Some(password_hash)) {
+    if super::try_init_user(&conn, username, display_name, &Some(password_hash))? {
         Ok(Status::Ok)
     }
     else {
-        Err(Status::Forbidden)
+        Err(Status::Forbidden.into())
     }
 }
 
 #[post("/auth/login/password", data = "<data>")]
-pub fn post_login_password(data: Json<Value>) -> Result <Json<Value>, Status> {
+pub fn post_login_password(data: Json<Value>, conn: DbConn) -> Result<Json<Value>, Error> {
     let username = data["username"].as_str()
         .ok_or(Status::BadRequest)?;
     let password = data["password"].as_str()
@@ -33,36 +36,50 @@ pub fn post_login_password(data: Json<Value>) -> Result <Json<Value>, Status> {
     let course = data["course"].as_str()
         .ok_or(Status::BadRequest)?;
 
-    if crate::course::name_to_title(&course).is_none() {
-        return Err(Status::NotFound);
+    if crate::course::name_to_title(&conn, course)?.is_none() {
+        return Err(Status::NotFound.into());
     }
 
     use crate::schema::users;
     let hash = users::table.filter(users::username.eq(username))
         .select(users::password)
-        .first::<Option<String>>(&crate::database_connection())
-        .or(Err(Status::NotFound))?
+        .first::<Option<String>>(&*conn)?
         .ok_or(Status::Forbidden)?;
 
     if !password_verify(password, &hash) {
-        return Err(Status::Unauthorized);
+        return Err(Status::Unauthorized.into());
+    }
+
+    if crate::course::management::is_archived(&conn, course)? {
+        return Err(Status::Forbidden.into());
+    }
+
+    // Users that are not enrolled yet may join with the course's enrollment key
+    if !crate::course::enrollments::is_enrolled(&conn, username, course)? {
+        let key = data["enrollmentKey"].as_str()
+            .ok_or(Status::Forbidden)?;
+
+        if !crate::course::enrollments::check_enrollment_key(&conn, course, key)? {
+            return Err(Status::Forbidden.into());
+        }
+
+        crate::course::enrollments::enroll(&conn, username, course, Role::Student)?;
     }
 
     Ok(Json(json!({
-        "token": super::create_session(username, course, &None)
+        "token": super::create_session(&conn, username, course, &None)?
     })))
 }
 
 #[put("/auth/password", data = "<data>")]
-pub fn put_password(user: guards::User, data: Json<Value>) -> Result<Status, Status> {
+pub fn put_password(user: guards::Session, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
     let new_password = data["newPassword"].as_str()
         .ok_or(Status::BadRequest)?;
 
     use crate::schema::users;
     diesel::update(users::table.filter(users::username.eq(user.name)))
         .set(users::password.eq(password_hash(new_password)))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(&*conn)?;
 
     Ok(Status::Ok)
 }
@@ -75,5 +92,5 @@ fn password_hash(password: &str) -> String {
 }
 
 fn password_verify(password: &str, hash: &str) -> bool {
-    argon2::verify_encoded(hash, password.as_bytes()).unwrap()
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
 }
\ No newline at end of file
@@ -0,0 +1,217 @@
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use serde_json::Value;
+use diesel::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+use crate::schema::enrollments;
+use crate::auth::guards;
+use crate::DbConn;
+use crate::error::Error;
+
+/// Role of a user in a course. Roles are ordered, i.e. every role has all
+/// permissions of the roles before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Student,
+    Tutor,
+    Instructor,
+    Admin
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Student => "student",
+            Role::Tutor => "tutor",
+            Role::Instructor => "instructor",
+            Role::Admin => "admin"
+        }
+    }
+
+    /// Maps LTI roles to the highest matching role. Understands the short
+    /// names and URNs of LTI 1.1 as well as the LIS vocabulary URIs of LTI 1.3.
+    /// Only context (membership) roles count; institution and system roles,
+    /// e.g. an LMS administrator, say nothing about the course.
+    pub fn from_lti_roles<'a>(roles: impl IntoIterator<Item = &'a str>) -> Role {
+        roles.into_iter()
+            .filter_map(|role| context_role(role.trim()))
+            .map(|role| match role {
+                (_, Some("TeachingAssistant")) | ("TeachingAssistant", _) => Role::Tutor,
+                ("Administrator", _) => Role::Admin,
+                ("Instructor", _) | ("ContentDeveloper", _) => Role::Instructor,
+                // Learner, Member, Mentor (parents and guardians), ...
+                _ => Role::Student
+            })
+            .max()
+            .unwrap_or(Role::Student)
+    }
+}
+
+/// Splits an LTI context role into role and sub role, e.g.
+/// "urn:lti:role:ims/lis/Instructor/PrimaryInstructor" or
+/// "http://purl.imsglobal.org/vocab/lis/v2/membership/Instructor#TeachingAssistant".
+/// Returns None for other roles.
+fn context_role(role: &str) -> Option<(&str, Option<&str>)> {
+    fn split(role: &str, separator: char) -> (&str, Option<&str>) {
+        match role.split_once(separator) {
+            Some((role, sub_role)) => (role, Some(sub_role)),
+            None => (role, None)
+        }
+    }
+
+    if let Some(role) = role.strip_prefix("urn:lti:role:ims/lis/") {
+        Some(split(role, '/'))
+    }
+    else if let Some(role) = role.strip_prefix("http://purl.imsglobal.org/vocab/lis/v2/membership") {
+        match role.strip_prefix('#') {
+            Some(role) => Some((role, None)),
+            None => role.strip_prefix('/').map(|role| split(role, '#'))
+        }
+    }
+    else if !role.contains(':') {
+        // Short names of LTI 1.1 are context roles
+        Some(split(role, '/'))
+    }
+    else {
+        None
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "student" => Ok(Role::Student),
+            "tutor" => Ok(Role::Tutor),
+            "instructor" => Ok(Role::Instructor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(Status::BadRequest.into())
+        }
+    }
+}
+
+/// Lists all enrollments of a course with their roles.
+#[get("/admin/courses/<course>/roles")]
+pub fn route_get_course_roles(_admin: guards::Admin, course: String, conn: DbConn) -> Result<Json<Value>, Error> {
+    let roles = enrollments::table.filter(enrollments::course.eq(&course))
+        .select((enrollments::username, enrollments::role, enrollments::enrolledAt))
+        .load::<(String, String, i64)>(&*conn)?
+        .into_iter()
+        .map(|(username, role, enrolled_at)| json!({ "username": username, "role": role, "enrolledAt": enrolled_at }))
+        .collect::<Vec<_>>();
+
+    Ok(Json(Value::Array(roles)))
+}
+
+/// Sets the role of a user in a course, enrolling the user if necessary.
+#[put("/admin/courses/<course>/roles/<username>", data = "<data>")]
+pub fn route_put_course_role(_admin: guards::Admin, course: String, username: String, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let role = data["role"].as_str()
+        .ok_or(Status::BadRequest)?
+        .parse::<Role>()?;
+
+    if crate::course::name_to_title(&conn, &course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+
+    set_role(&conn, &username, &course, role)?;
+
+    Ok(Status::Ok)
+}
+
+/// Removes a user from a course.
+#[delete("/admin/courses/<course>/roles/<username>")]
+pub fn route_delete_course_role(_admin: guards::Admin, course: String, username: String, conn: DbConn) -> Result<Status, Error> {
+    let deleted = diesel::delete(enrollments::table.filter(enrollments::username.eq(&username)))
+        .filter(enrollments::course.eq(&course))
+        .execute(&*conn)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound.into());
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Returns the role of a user in a course. Users that are not enrolled are
+/// students, users with admin flag are admins in every course.
+pub fn course_role(conn: &MysqlConnection, username: &str, course: &str) -> Result<Role, Error> {
+    if super::admin::is_admin(conn, username)? {
+        return Ok(Role::Admin);
+    }
+
+    let role = enrollments::table.filter(enrollments::username.eq(username))
+        .filter(enrollments::course.eq(course))
+        .select(enrollments::role)
+        .first::<String>(conn)
+        .optional()?;
+
+    match role {
+        Some(role) => role.parse(),
+        None => Ok(Role::Student)
+    }
+}
+
+/// Sets the role of a user in a course, enrolling the user if necessary.
+pub fn set_role(conn: &MysqlConnection, username: &str, course: &str, role: Role) -> Result<(), Error> {
+    if !crate::course::enrollments::enroll(conn, username, course, role)? {
+        diesel::update(enrollments::table.filter(enrollments::username.eq(username)))
+            .filter(enrollments::course.eq(course))
+            .set(enrollments::role.eq(role.as_str()))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the role from an LTI launch and enrolls the user if necessary. LTI
+/// launches only ever raise the role, so roles assigned by an admin survive
+/// the next launch.
+pub fn raise_role(conn: &MysqlConnection, username: &str, course: &str, role: Role) -> Result<(), Error> {
+    if crate::course::enrollments::enroll(conn, username, course, role)? {
+        return Ok(());
+    }
+
+    if course_role(conn, username, course)? < role {
+        set_role(conn, username, course, role)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lti_roles() {
+        assert_eq!(Role::from_lti_roles(vec![]), Role::Student);
+        assert_eq!(Role::from_lti_roles("Learner".split(',')), Role::Student);
+        assert_eq!(Role::from_lti_roles("Learner,urn:lti:role:ims/lis/TeachingAssistant".split(',')), Role::Tutor);
+        assert_eq!(Role::from_lti_roles("urn:lti:role:ims/lis/Instructor/PrimaryInstructor".split(',')), Role::Instructor);
+        assert_eq!(Role::from_lti_roles("Instructor,urn:lti:instrole:ims/lis/Administrator".split(',')), Role::Instructor);
+        assert_eq!(Role::from_lti_roles("urn:lti:role:ims/lis/Administrator".split(',')), Role::Admin);
+        assert_eq!(Role::from_lti_roles("Learner,urn:lti:role:ims/lis/Mentor".split(',')), Role::Student);
+        assert_eq!(Role::from_lti_roles(vec![
+            "http://purl.imsglobal.org/vocab/lis/v2/system/person#Administrator",
+            "http://purl.imsglobal.org/vocab/lis/v2/institution/person#Administrator",
+            "http://purl.imsglobal.org/vocab/lis/v2/membership#Learner"
+        ]), Role::Student);
+        assert_eq!(Role::from_lti_roles(vec![
+            "http://purl.imsglobal.org/vocab/lis/v2/membership/Instructor#TeachingAssistant"
+        ]), Role::Tutor);
+        assert_eq!(Role::from_lti_roles(vec![
+            "http://purl.imsglobal.org/vocab/lis/v2/membership#Instructor",
+            "http://purl.imsglobal.org/vocab/lis/v2/membership#Learner"
+        ]), Role::Instructor);
+    }
+}
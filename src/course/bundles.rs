@@ -0,0 +1,515 @@
+use diesel::prelude::*;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use crate::auth::guards;
+use crate::schema::{courses, courseTask, tasks};
+use crate::{SETTINGS, DbConn};
+use crate::error::Error;
+use crate::evaluation::Queue;
+use super::tasks::{save_task, Mapping, Task};
+
+/// A task with all of its course mappings, as stored in a bundle.
+#[derive(Debug, PartialEq)]
+pub(crate) struct BundleTask {
+    task: Task,
+    mappings: Vec<Mapping>
+}
+
+/// Limits for unpacking a bundle, see `bundles` in the settings.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BundleLimits {
+    /// Bytes of the unpacked archive
+    pub size: u64,
+    /// Bytes per file
+    pub file_size: u64,
+    pub entries: usize
+}
+
+impl BundleLimits {
+    pub fn from_settings() -> BundleLimits {
+        let mib = |key: &str| SETTINGS.get::<u64>(key)
+            .unwrap_or_else(|_| panic!("{} missing in settings", key)) * 1024 * 1024;
+
+        BundleLimits {
+            size: mib("bundles.max_size"),
+            file_size: mib("bundles.max_file_size"),
+            entries: SETTINGS.get::<usize>("bundles.max_entries")
+                .expect("bundles.max_entries missing in settings")
+        }
+    }
+}
+
+/// Content of `task.json`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskFile {
+    taskid: i32,
+    lang: String,
+    #[serde(default)]
+    task_description: Value,
+    #[serde(default)]
+    courses: Vec<CourseFile>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CourseFile {
+    course_name: String,
+    #[serde(default = "empty_list")]
+    tags: Value,
+    order_by: i32,
+    #[serde(default = "empty_list")]
+    prerequisites: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    visible_from: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deadline: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    late_until: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group_id: Option<i32>
+}
+
+/// What an import changes for a task. `changes` lists the changed fields
+/// (taskDescription, solution, lang, tests, courses).
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TaskDiff {
+    taskid: i32,
+    /// "new", "changed" or "unchanged"
+    status: &'static str,
+    changes: Vec<&'static str>
+}
+
+/// Imports a task bundle: a tar archive (optionally gzipped) with one
+/// directory per task, e.g. from a git repository of task authors:
+///
+/// ```text
+/// <dir>/task.json       {"taskid": 42, "lang": "python3", "taskDescription": {...},
+///                        "courses": [{"courseName": "...", "tags": [...], "orderBy": 1, "prerequisites": [...],
+///                                     "visibleFrom": ..., "deadline": ..., "lateUntil": ..., "groupId": ...}]}
+/// <dir>/description.md  optional, replaces taskDescription.description
+/// <dir>/tests.json      tests in the format of the evaluator
+/// <dir>/solution.*      reference solution, any extension
+/// ```
+///
+/// Like in route_post_task, `courses` replaces all mappings of the task and
+/// every new or changed task is stored as new version. All tasks are imported
+/// in one transaction, or none if the prerequisite graph of a course becomes
+/// invalid (400) or a reference solution of a new or changed task fails
+/// (422, skipped with `validate=false`). With `dry_run=true` only
+/// the diff is returned. The size of the archive is limited by the `bundle`
+/// limit of the Rocket config (default 16 MiB), its unpacked content by the
+/// `bundles` settings.
+#[post("/admin/tasks/import?<dry_run>&<validate>", data = "<data>")]
+pub async fn route_post_import(admin: guards::Admin, dry_run: Option<bool>, validate: Option<bool>, data: Data<'_>, limits: &Limits, conn: DbConn, queue: &State<Queue>) -> Result<(Status, Json<Value>), Error> {
+    let archive = data.open(limits.get("bundle").unwrap_or_else(|| 16.mebibytes()))
+        .into_bytes()
+        .await
+        .map_err(bundle_error)?;
+    if !archive.is_complete() {
+        return Err(Status::PayloadTooLarge.into());
+    }
+    let bundle = read_bundle(&archive, BundleLimits::from_settings())?;
+
+    let courses = bundle.iter()
+        .flat_map(|task| task.mappings.iter().map(|mapping| mapping.course.as_str()))
+        .collect::<BTreeSet<_>>();
+    let known = courses::table.filter(courses::name.eq_any(&courses))
+        .select(courses::name)
+        .load::<String>(&*conn)?;
+    if let Some(course) = courses.iter().find(|course| !known.iter().any(|known| known == *course)) {
+        return Err(Error::Bundle(format!("Unknown course {}", course)));
+    }
+    let changes = bundle.iter()
+        .map(|task| (task.task.taskid, task.mappings.as_slice()))
+        .collect::<Vec<_>>();
+    super::prerequisites::check_graphs(&conn, &changes)?;
+
+    let mut existing = tasks::table.filter(tasks::taskid.eq_any(bundle.iter().map(|task| task.task.taskid)))
+        .load::<Task>(&*conn)?
+        .into_iter()
+        .map(|task| (task.taskid, task))
+        .collect::<BTreeMap<_, _>>();
+    let existing_mappings = courseTask::table.filter(courseTask::taskid.eq_any(bundle.iter().map(|task| task.task.taskid)))
+        .load::<Mapping>(&*conn)?;
+    let diffs = bundle.iter()
+        .map(|task| compare(existing.remove(&task.task.taskid).as_ref(), &existing_mappings, task))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if validate.unwrap_or(true) {
+        let mut failed = Vec::new();
+        for (task, diff) in bundle.iter().zip(&diffs) {
+            if !diff.changes.iter().any(|field| ["solution", "lang", "tests"].contains(field)) {
+                continue;
+            }
+
+            let task = &task.task;
+            let result = queue.evaluator()
+                .evaluate(task.taskid, &task.lang, &serde_json::from_str(&task.tests)?, &task.solution)
+                .await?;
+            if result.result_type != "SUCCESS" {
+                failed.push(json!({
+                    "taskid": task.taskid,
+                    "validation": {
+                        "type": result.result_type,
+                        "score": result.score,
+                        "simplified": result.simplified,
+                        "details": result.details
+                    }
+                }));
+            }
+        }
+
+        if !failed.is_empty() {
+            return Ok((Status::UnprocessableEntity, Json(json!({
+                "code": "solution_failed",
+                "message": "Reference solutions of the bundle do not pass their tests",
+                "changes": diffs,
+                "failed": failed
+            }))));
+        }
+    }
+
+    let dry_run = dry_run.unwrap_or(false);
+    if !dry_run {
+        conn.transaction::<_, Error, _>(|| {
+            for (task, _) in bundle.iter().zip(&diffs).filter(|(_, diff)| diff.status != "unchanged") {
+                save_task(&conn, &task.task, &task.mappings, &admin.name)?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok((Status::Ok, Json(json!({
+        "dryRun": dry_run,
+        "changes": diffs
+    }))))
+}
+
+/// Exports all tasks of a course as gzipped bundle (see route_post_import),
+/// including their mappings to other courses.
+#[get("/admin/courses/<course>/tasks/export")]
+pub fn route_get_export(_admin: guards::Admin, course: String, conn: DbConn) -> Result<(ContentType, Vec<u8>), Error> {
+    if super::name_to_title(&conn, &course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+
+    let taskids = courseTask::table.filter(courseTask::course.eq(&course))
+        .select(courseTask::taskid)
+        .load::<i32>(&*conn)?;
+    let mappings = courseTask::table.filter(courseTask::taskid.eq_any(&taskids))
+        .load::<Mapping>(&*conn)?;
+    let bundle = tasks::table.filter(tasks::taskid.eq_any(&taskids))
+        .order(tasks::taskid)
+        .load::<Task>(&*conn)?
+        .into_iter()
+        .map(|task| BundleTask {
+            mappings: mappings.iter()
+                .filter(|mapping| mapping.taskid == task.taskid)
+                .cloned()
+                .collect(),
+            task
+        })
+        .collect::<Vec<_>>();
+
+    Ok((ContentType::new("application", "gzip"), write_bundle(&bundle)?))
+}
+
+pub(crate) fn read_bundle(archive: &[u8], limits: BundleLimits) -> Result<Vec<BundleTask>, Error> {
+    let reader: Box<dyn Read> = if archive.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(archive))
+    }
+    else {
+        Box::new(archive)
+    };
+
+    let mut reader = reader.take(limits.size);
+    let files = read_files(&mut reader, limits);
+    if reader.limit() == 0 {
+        return Err(Error::Bundle(format!("Unpacked bundle is larger than {} bytes", limits.size)));
+    }
+    let files = files?;
+
+    let dirs = files.keys()
+        .filter(|path| path.file_name().is_some_and(|name| name == "task.json"))
+        .map(|path| path.parent().unwrap_or_else(|| Path::new("")).to_path_buf())
+        .collect::<Vec<_>>();
+    if dirs.is_empty() {
+        return Err(Error::Bundle("No task.json found".to_string()));
+    }
+
+    let mut taskids = BTreeSet::new();
+    dirs.iter()
+        .map(|dir| {
+            let task = read_task(&files, dir)
+                .map_err(|err| Error::Bundle(format!("{}: {}", dir.display(), err)))?;
+            if !taskids.insert(task.task.taskid) {
+                return Err(Error::Bundle(format!("Duplicate taskid {}", task.task.taskid)));
+            }
+            Ok(task)
+        })
+        .collect()
+}
+
+fn read_files(reader: impl Read, limits: BundleLimits) -> Result<BTreeMap<PathBuf, String>, Error> {
+    let mut files = BTreeMap::new();
+    for (index, entry) in tar::Archive::new(reader).entries().map_err(bundle_error)?.enumerate() {
+        if index >= limits.entries {
+            return Err(Error::Bundle(format!("Bundle has more than {} entries", limits.entries)));
+        }
+        let mut entry = entry.map_err(bundle_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path().map_err(bundle_error)?.to_path_buf();
+        if entry.size() > limits.file_size {
+            return Err(Error::Bundle(format!("{}: larger than {} bytes", path.display(), limits.file_size)));
+        }
+        let mut content = String::new();
+        entry.read_to_string(&mut content)
+            .map_err(|err| Error::Bundle(format!("{}: {}", path.display(), err)))?;
+        files.insert(path, content);
+    }
+
+    Ok(files)
+}
+
+fn read_task(files: &BTreeMap<PathBuf, String>, dir: &Path) -> Result<BundleTask, String> {
+    let file = |name: &str| files.get(&dir.join(name));
+
+    let mut meta = serde_json::from_str::<TaskFile>(file("task.json").expect("Directories are found by their task.json"))
+        .map_err(|err| format!("task.json: {}", err))?;
+    if let Some(description) = file("description.md") {
+        if meta.task_description.is_null() {
+            meta.task_description = json!({});
+        }
+        meta.task_description.as_object_mut()
+            .ok_or("taskDescription must be an object")?
+            .insert("description".to_string(), Value::String(description.clone()));
+    }
+
+    let tests = serde_json::from_str::<Value>(file("tests.json").ok_or("tests.json missing")?)
+        .map_err(|err| format!("tests.json: {}", err))?;
+
+    let mut solutions = files.iter()
+        .filter(|(path, _)| path.parent() == Some(dir) && path.file_stem().is_some_and(|stem| stem == "solution"));
+    let solution = match (solutions.next(), solutions.next()) {
+        (Some((_, solution)), None) => solution.clone(),
+        (None, _) => return Err("Solution missing".to_string()),
+        (Some(_), Some(_)) => return Err("More than one solution file".to_string())
+    };
+
+    let mappings = meta.courses.iter()
+        .map(|course| Mapping {
+            course: course.course_name.clone(),
+            taskid: meta.taskid,
+            tags: course.tags.to_string(),
+            order_by: course.order_by,
+            prerequisites: course.prerequisites.to_string(),
+            visible_from: course.visible_from,
+            deadline: course.deadline,
+            late_until: course.late_until,
+            group_id: course.group_id
+        })
+        .collect();
+
+    Ok(BundleTask {
+        task: Task {
+            taskid: meta.taskid,
+            task_description: meta.task_description.to_string(),
+            solution,
+            lang: meta.lang,
+            tests: tests.to_string(),
+            version: 0
+        },
+        mappings
+    })
+}
+
+/// Writes a gzipped bundle with one directory per task, named by its taskid.
+/// A string `taskDescription.description` is written to description.md.
+pub(crate) fn write_bundle(bundle: &[BundleTask]) -> Result<Vec<u8>, Error> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+    for BundleTask { task, mappings } in bundle {
+        let mut task_description = serde_json::from_str::<Value>(&task.task_description)?;
+        let description = match task_description.get("description") {
+            Some(Value::String(description)) => Some(description.clone()),
+            _ => None
+        };
+        if description.is_some() {
+            task_description.as_object_mut()
+                .expect("Has a description field")
+                .remove("description");
+        }
+
+        let meta = TaskFile {
+            taskid: task.taskid,
+            lang: task.lang.clone(),
+            task_description,
+            courses: mappings.iter()
+                .map(|mapping| Ok(CourseFile {
+                    course_name: mapping.course.clone(),
+                    tags: serde_json::from_str(&mapping.tags)?,
+                    order_by: mapping.order_by,
+                    prerequisites: serde_json::from_str(&mapping.prerequisites)?,
+                    visible_from: mapping.visible_from,
+                    deadline: mapping.deadline,
+                    late_until: mapping.late_until,
+                    group_id: mapping.group_id
+                }))
+                .collect::<Result<_, Error>>()?
+        };
+
+        let dir = PathBuf::from(task.taskid.to_string());
+        append(&mut builder, &dir.join("task.json"), &serde_json::to_string_pretty(&meta)?)?;
+        if let Some(description) = description {
+            append(&mut builder, &dir.join("description.md"), &description)?;
+        }
+        append(&mut builder, &dir.join("tests.json"), &serde_json::to_string_pretty(&serde_json::from_str::<Value>(&task.tests)?)?)?;
+        append(&mut builder, &dir.join("solution"), &task.solution)?;
+    }
+
+    builder.into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(bundle_error)
+}
+
+fn append<W: io::Write>(builder: &mut tar::Builder<W>, path: &Path, content: &str) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, path, content.as_bytes())
+        .map_err(bundle_error)
+}
+
+/// Compares a bundle task with the stored task and its mappings. JSON fields
+/// are compared by value, so formatting differences are no changes.
+fn compare(existing: Option<&Task>, existing_mappings: &[Mapping], new: &BundleTask) -> Result<TaskDiff, Error> {
+    let taskid = new.task.taskid;
+    let existing = match existing {
+        Some(existing) => existing,
+        None => return Ok(TaskDiff { taskid, status: "new", changes: vec!["taskDescription", "solution", "lang", "tests", "courses"] })
+    };
+
+    let same_json = |a: &str, b: &str| -> Result<bool, Error> {
+        Ok(serde_json::from_str::<Value>(a)? == serde_json::from_str::<Value>(b)?)
+    };
+    let mapping_values = |mappings: &mut dyn Iterator<Item = &Mapping>| -> Result<BTreeMap<String, Value>, Error> {
+        mappings.map(|mapping| Ok((mapping.course.clone(), mapping.to_json()?))).collect()
+    };
+
+    let mut changes = Vec::new();
+    if !same_json(&existing.task_description, &new.task.task_description)? {
+        changes.push("taskDescription");
+    }
+    if existing.solution != new.task.solution {
+        changes.push("solution");
+    }
+    if existing.lang != new.task.lang {
+        changes.push("lang");
+    }
+    if !same_json(&existing.tests, &new.task.tests)? {
+        changes.push("tests");
+    }
+    if mapping_values(&mut existing_mappings.iter().filter(|mapping| mapping.taskid == taskid))? != mapping_values(&mut new.mappings.iter())? {
+        changes.push("courses");
+    }
+
+    Ok(TaskDiff {
+        taskid,
+        status: if changes.is_empty() { "unchanged" } else { "changed" },
+        changes
+    })
+}
+
+fn empty_list() -> Value {
+    json!([])
+}
+
+fn bundle_error(err: io::Error) -> Error {
+    Error::Bundle(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: BundleLimits = BundleLimits { size: 1024 * 1024, file_size: 1024, entries: 10 };
+
+    fn bundle_task() -> BundleTask {
+        BundleTask {
+            task: Task {
+                taskid: 42,
+                task_description: json!({ "shortDescription": "Sum", "description": "# Sum\nAdd two numbers." }).to_string(),
+                solution: "read a b; echo $((a + b))\n".to_string(),
+                lang: "sh".to_string(),
+                tests: json!([{ "input": "1 2", "output": "3" }]).to_string(),
+                version: 1
+            },
+            mappings: vec![Mapping {
+                course: "testbeans".to_string(),
+                taskid: 42,
+                tags: json!(["basics"]).to_string(),
+                order_by: 1,
+                prerequisites: json!([]).to_string(),
+                visible_from: Some(1634000000),
+                deadline: None,
+                late_until: None,
+                group_id: None
+            }]
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bundle = vec![bundle_task()];
+        let read = read_bundle(&write_bundle(&bundle).unwrap(), LIMITS).unwrap();
+        assert_eq!(read.len(), 1);
+
+        let diff = compare(Some(&bundle[0].task), &bundle[0].mappings, &read[0]).unwrap();
+        assert_eq!(diff, TaskDiff { taskid: 42, status: "unchanged", changes: vec![] });
+        assert_eq!(read[0].task.solution, bundle[0].task.solution);
+    }
+
+    #[test]
+    fn changes() {
+        let mut new = bundle_task();
+        new.task.tests = json!([{ "input": "1 2", "output": "4" }]).to_string();
+        new.mappings[0].order_by = 2;
+        let existing = bundle_task();
+
+        let diff = compare(Some(&existing.task), &existing.mappings, &new).unwrap();
+        assert_eq!(diff.status, "changed");
+        assert_eq!(diff.changes, vec!["tests", "courses"]);
+
+        assert_eq!(compare(None, &[], &new).unwrap().status, "new");
+    }
+
+    #[test]
+    fn limits() {
+        let mut task = bundle_task();
+        task.task.solution = "echo 3\n".repeat(1000);
+        let archive = write_bundle(&[task]).unwrap();
+
+        let message = |limits| match read_bundle(&archive, limits) {
+            Err(Error::Bundle(message)) => message,
+            result => panic!("Unexpected result {:?}", result)
+        };
+        assert!(message(LIMITS).ends_with("larger than 1024 bytes"));
+        assert_eq!(message(BundleLimits { size: 4096, file_size: 8192, ..LIMITS }), "Unpacked bundle is larger than 4096 bytes");
+        assert_eq!(message(BundleLimits { entries: 2, ..LIMITS }), "Bundle has more than 2 entries");
+        assert!(read_bundle(&archive, BundleLimits { file_size: 8192, ..LIMITS }).is_ok());
+    }
+}
@@ -0,0 +1,97 @@
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use serde_json::Value;
+use diesel::prelude::*;
+use crate::schema::{courses, enrollments};
+use crate::auth::guards;
+use crate::auth::roles::Role;
+use crate::DbConn;
+use crate::error::Error;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserCourse {
+    pub name: String,
+    pub title: String,
+    pub role: String,
+    pub enrolled_at: i64
+}
+
+/// Lists all (not archived) courses the user is enrolled in.
+#[get("/user/courses")]
+pub fn route_get_user_courses(session: guards::Session, conn: DbConn) -> Result<Json<Vec<UserCourse>>, Error> {
+    Ok(Json(user_courses(&conn, &session.name)?))
+}
+
+/// Self-enrollment with the enrollment key of the course, body: {"key": "..."}.
+/// Courses without enrollment key don't allow self-enrollment.
+#[post("/courses/<course>/enroll", data = "<data>")]
+pub fn route_post_enroll(session: guards::Session, course: String, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let key = data["key"].as_str()
+        .ok_or(Status::BadRequest)?;
+
+    if !check_enrollment_key(&conn, &course, key)? {
+        return Err(Status::Forbidden.into());
+    }
+
+    enroll(&conn, &session.name, &course, Role::Student)?;
+
+    Ok(Status::Ok)
+}
+
+/// Returns true if the user is enrolled in the course. Admins are members of every course.
+pub fn is_enrolled(conn: &MysqlConnection, username: &str, course: &str) -> Result<bool, Error> {
+    if crate::auth::admin::is_admin(conn, username)? {
+        return Ok(true);
+    }
+
+    Ok(enrollments::table.filter(enrollments::username.eq(username))
+        .filter(enrollments::course.eq(course))
+        .select(enrollments::username)
+        .first::<String>(conn)
+        .optional()?
+        .is_some())
+}
+
+/// Returns all (not archived) courses the user is enrolled in.
+pub fn user_courses(conn: &MysqlConnection, username: &str) -> Result<Vec<UserCourse>, Error> {
+    let courses = enrollments::table.inner_join(courses::table.on(courses::name.eq(enrollments::course)))
+        .filter(enrollments::username.eq(username))
+        .filter(courses::archived.eq(false))
+        .select((courses::name, courses::title, enrollments::role, enrollments::enrolledAt))
+        .order(enrollments::enrolledAt)
+        .load::<(String, String, String, i64)>(conn)?
+        .into_iter()
+        .map(|(name, title, role, enrolled_at)| UserCourse { name, title, role, enrolled_at })
+        .collect();
+
+    Ok(courses)
+}
+
+/// Enrolls a user with the given role. Returns false (and leaves the role
+/// untouched) if the user is already enrolled.
+pub fn enroll(conn: &MysqlConnection, username: &str, course: &str, role: Role) -> Result<bool, Error> {
+    let inserted = diesel::insert_or_ignore_into(enrollments::table)
+        .values((
+            enrollments::username.eq(username),
+            enrollments::course.eq(course),
+            enrollments::role.eq(role.as_str()),
+            enrollments::enrolledAt.eq(crate::tools::epoch())
+        ))
+        .execute(conn)?;
+
+    Ok(inserted > 0)
+}
+
+/// Returns true if the course can be joined with the given key.
+pub fn check_enrollment_key(conn: &MysqlConnection, course: &str, key: &str) -> Result<bool, Error> {
+    let course = courses::table.filter(courses::name.eq(course))
+        .select((courses::enrollmentKey, courses::archived))
+        .first::<(Option<String>, bool)>(conn)
+        .optional()?;
+
+    Ok(match course {
+        Some((Some(enrollment_key), false)) => enrollment_key == key,
+        _ => false
+    })
+}
@@ -0,0 +1,286 @@
+use diesel::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::schema::{courses, courseTask, submissions};
+use crate::error::Error;
+use crate::evaluation::NO_RESULT;
+use super::groups::{self, TaskGroup};
+use super::tasks::Mapping;
+
+/// How task results add up to the course grade. Read from the `grading` key
+/// of the course config, e.g. `{"grading": {"mode": "score", "weights": {"42": 2}, "latePenalty": 0.5}}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct GradingConfig {
+    #[serde(default)]
+    pub mode: GradingMode,
+    /// Weight per task id; tasks without an entry have weight 1
+    #[serde(default)]
+    pub weights: HashMap<i32, f64>,
+    /// Share of the value a late submission loses (0.0 - 1.0), e.g. 0.5 for half the points
+    #[serde(default, rename = "latePenalty")]
+    pub late_penalty: f64
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradingMode {
+    /// A task counts fully as soon as it has a successful submission
+    #[default]
+    Solved,
+    /// A task counts with the best score of all submissions (0.0 - 1.0)
+    Score
+}
+
+/// Progress of a user in a single task, part of the course progress
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskProgress {
+    pub taskid: i32,
+    /// Highest score of all submissions, null without submissions
+    pub best_score: Option<f32>,
+    pub attempts: usize,
+    /// Timestamp of the first successful submission
+    pub first_solved: Option<i64>,
+    pub status: TaskStatus,
+    /// Value of the task towards the grade (see GradingConfig::grade), at most its weight
+    pub points: f64,
+    pub max_points: f64
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskStatus {
+    /// No submissions yet
+    Open,
+    /// Submitted, but not successful yet
+    Attempted,
+    /// Successful submission before the deadline
+    Solved,
+    /// Only successful after the deadline
+    SolvedLate
+}
+
+impl GradingConfig {
+    pub fn weight(&self, taskid: i32) -> f64 {
+        self.weights.get(&taskid).copied().unwrap_or(1.0)
+    }
+
+    /// Returns the grade (0.0 - 1.0) for the given course tasks and a list of
+    /// (taskid, resultType, score, late) submission tuples.
+    pub fn grade(&self, taskids: &[i32], submissions: &[(i32, String, f32, bool)]) -> f64 {
+        let total = taskids.iter().map(|id| self.weight(*id)).sum::<f64>();
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        let achieved = taskids.iter()
+            .map(|id| {
+                let value = submissions.iter()
+                    .filter(|(taskid, _, _, _)| taskid == id)
+                    .map(|(_, result_type, score, late)| self.value(result_type, *score, *late))
+                    .fold(0.0, f64::max);
+                value * self.weight(*id)
+            })
+            .sum::<f64>();
+
+        achieved / total
+    }
+
+    /// Progress in a task from a list of (taskid, resultType, score, late, timestamp)
+    /// submission tuples; submissions for other tasks and submissions without
+    /// a result (see evaluation::NO_RESULT) are ignored.
+    pub fn task_progress(&self, taskid: i32, submissions: &[(i32, String, f32, bool, i64)]) -> TaskProgress {
+        let submissions = submissions.iter()
+            .filter(|(id, _, _, _, _)| *id == taskid)
+            .filter(|(_, result_type, _, _, _)| !NO_RESULT.contains(&result_type.as_str()))
+            .collect::<Vec<_>>();
+        let successful = submissions.iter()
+            .filter(|(_, result_type, _, _, _)| result_type == "SUCCESS")
+            .collect::<Vec<_>>();
+
+        let status = if successful.iter().any(|(_, _, _, late, _)| !late) {
+            TaskStatus::Solved
+        } else if !successful.is_empty() {
+            TaskStatus::SolvedLate
+        } else if !submissions.is_empty() {
+            TaskStatus::Attempted
+        } else {
+            TaskStatus::Open
+        };
+        let value = submissions.iter()
+            .map(|(_, result_type, score, late, _)| self.value(result_type, *score, *late))
+            .fold(0.0, f64::max);
+
+        TaskProgress {
+            taskid,
+            best_score: submissions.iter().map(|(_, _, score, _, _)| *score).reduce(f32::max),
+            attempts: submissions.len(),
+            first_solved: successful.iter().map(|(_, _, _, _, timestamp)| *timestamp).min(),
+            status,
+            points: value * self.weight(taskid),
+            max_points: self.weight(taskid)
+        }
+    }
+
+    /// Value (0.0 - 1.0) of a single submission, after the late penalty
+    fn value(&self, result_type: &str, score: f32, late: bool) -> f64 {
+        let value = match self.mode {
+            GradingMode::Solved => if result_type == "SUCCESS" { 1.0 } else { 0.0 },
+            GradingMode::Score => (score as f64).clamp(0.0, 1.0)
+        };
+        if late { value * (1.0 - self.late_penalty.clamp(0.0, 1.0)) } else { value }
+    }
+}
+
+pub fn grading_config(conn: &MysqlConnection, course: &str) -> Result<GradingConfig, Error> {
+    let config = courses::table.filter(courses::name.eq(course))
+        .select(courses::config)
+        .first::<String>(conn)?;
+    let config: Value = serde_json::from_str(&config)?;
+
+    match config.get("grading") {
+        Some(grading) if !grading.is_null() => Ok(serde_json::from_value(grading.clone())?),
+        _ => Ok(GradingConfig::default())
+    }
+}
+
+/// Tasks of a course that count towards the grade and the course progress,
+/// ordered by orderBy. Unreleased tasks (see groups::is_released) don't count.
+pub(crate) fn graded_tasks(conn: &MysqlConnection, course: &str) -> Result<Vec<Mapping>, Error> {
+    let mappings = courseTask::table.filter(courseTask::course.eq(course))
+        .load::<Mapping>(conn)?;
+    let groups = groups::course_groups(conn, course)?;
+
+    Ok(released(mappings, &groups, crate::tools::epoch()))
+}
+
+fn released(mut mappings: Vec<Mapping>, groups: &[TaskGroup], now: i64) -> Vec<Mapping> {
+    mappings.retain(|mapping| groups::is_released(mapping, groups, now));
+    mappings.sort_by_key(|mapping| (mapping.order_by, mapping.taskid));
+    mappings
+}
+
+/// Calculates the current course grade (0.0 - 1.0) of a user.
+pub fn course_grade(conn: &MysqlConnection, user: &str, course: &str) -> Result<f64, Error> {
+    let config = grading_config(conn, course)?;
+
+    let taskids = graded_tasks(conn, course)?.iter()
+        .map(|mapping| mapping.taskid)
+        .collect::<Vec<_>>();
+
+    let submissions = submissions::table.filter(submissions::user.eq(user))
+        .filter(submissions::course.eq(course))
+        .select((submissions::taskid, submissions::resultType, submissions::score, submissions::late))
+        .load::<(i32, String, f32, bool)>(conn)?;
+
+    Ok(config.grade(&taskids, &submissions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_grades() {
+        let submissions = vec![
+            (1, "SUCCESS".to_string(), 1.0, false),
+            (2, "WRONG_ANSWER".to_string(), 0.5, false),
+            (2, "WRONG_ANSWER".to_string(), 0.25, false)
+        ];
+
+        let config: GradingConfig = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(config.grade(&[1, 2, 3, 4], &submissions), 0.25);
+
+        let config: GradingConfig = serde_json::from_value(json!({
+            "mode": "score",
+            "weights": { "2": 2.0, "4": 0 }
+        })).unwrap();
+        assert_eq!(config.grade(&[1, 2, 3, 4], &submissions), 0.5);
+    }
+
+    #[test]
+    fn late_penalty() {
+        let submissions = vec![
+            (1, "SUCCESS".to_string(), 1.0, true),
+            (2, "SUCCESS".to_string(), 1.0, true),
+            (2, "WRONG_ANSWER".to_string(), 0.5, false)
+        ];
+
+        let config: GradingConfig = serde_json::from_value(json!({ "latePenalty": 0.5 })).unwrap();
+        assert_eq!(config.grade(&[1, 2], &submissions), 0.5);
+
+        let config: GradingConfig = serde_json::from_value(json!({ "mode": "score", "latePenalty": 0.75 })).unwrap();
+        assert_eq!(config.grade(&[1, 2], &submissions), 0.375);
+    }
+
+    #[test]
+    fn unreleased_tasks() {
+        let mapping = |taskid, visible_from, group_id| Mapping {
+            course: "testbeans".to_string(),
+            taskid,
+            tags: "[]".to_string(),
+            order_by: taskid,
+            prerequisites: "[]".to_string(),
+            visible_from,
+            deadline: None,
+            late_until: None,
+            group_id
+        };
+        let group = |id, visible_from| TaskGroup {
+            id,
+            course: "testbeans".to_string(),
+            title: format!("Sheet {}", id),
+            description: String::new(),
+            order_by: id,
+            visible_from,
+            pass_threshold: 1.0
+        };
+        let mappings = vec![mapping(3, None, Some(2)), mapping(1, None, Some(1)), mapping(2, Some(200), None)];
+        let groups = vec![group(1, Some(50)), group(2, Some(150))];
+        let submissions = vec![(1, "SUCCESS".to_string(), 1.0, false)];
+
+        let taskids = |now| released(mappings.clone(), &groups, now).iter()
+            .map(|mapping| mapping.taskid)
+            .collect::<Vec<_>>();
+        assert_eq!(taskids(100), vec![1]);
+        assert_eq!(taskids(300), vec![1, 2, 3]);
+
+        let config = GradingConfig::default();
+        assert_eq!(config.grade(&taskids(100), &submissions), 1.0);
+        assert_eq!(config.grade(&taskids(300), &submissions), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn task_progress() {
+        let submissions = vec![
+            (1, "WRONG_ANSWER".to_string(), 0.5, false, 100),
+            (1, "SUCCESS".to_string(), 1.0, true, 300),
+            (1, "SUCCESS".to_string(), 1.0, true, 200),
+            (2, "WRONG_ANSWER".to_string(), 0.25, false, 100),
+            (2, "EVALUATION_UNAVAILABLE".to_string(), 0.0, false, 200),
+            (3, "EVALUATION_UNAVAILABLE".to_string(), 0.0, false, 100),
+            (3, "PENDING".to_string(), 0.0, false, 200)
+        ];
+        let config: GradingConfig = serde_json::from_value(json!({
+            "mode": "score",
+            "weights": { "1": 2.0 },
+            "latePenalty": 0.25
+        })).unwrap();
+
+        assert_eq!(config.task_progress(1, &submissions), TaskProgress {
+            taskid: 1,
+            best_score: Some(1.0),
+            attempts: 3,
+            first_solved: Some(200),
+            status: TaskStatus::SolvedLate,
+            points: 1.5,
+            max_points: 2.0
+        });
+
+        let progress = config.task_progress(2, &submissions);
+        assert_eq!((progress.status, progress.attempts, progress.points), (TaskStatus::Attempted, 1, 0.25));
+
+        let progress = config.task_progress(3, &submissions);
+        assert_eq!((progress.status, progress.attempts, progress.best_score, progress.points), (TaskStatus::Open, 0, None, 0.0));
+    }
+}
@@ -0,0 +1,220 @@
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use crate::auth::guards;
+use crate::auth::roles::{course_role, Role};
+use crate::schema::{courseTask, taskGroups};
+use crate::DbConn;
+use crate::error::Error;
+use super::tasks::Mapping;
+
+/// Group of tasks within a course, e.g. a weekly exercise sheet or a chapter.
+#[derive(Debug, Clone, Serialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskGroup {
+    pub id: i32,
+    pub course: String,
+    pub title: String,
+    pub description: String,
+    pub order_by: i32,
+    /// The group and all of its tasks are hidden from students before this time
+    pub visible_from: Option<i64>,
+    /// Share of the tasks (0.0 - 1.0) that has to be solved to pass the group
+    pub pass_threshold: f32
+}
+
+impl TaskGroup {
+    pub fn is_visible(&self, now: i64) -> bool {
+        self.visible_from.is_none_or(|visible_from| visible_from <= now)
+    }
+}
+
+/// Groups of a course with the ids of their tasks. Unreleased groups and tasks
+/// are only included for tutors and above.
+#[get("/courses/<course>/groups")]
+pub fn route_get_groups(user: guards::User, course: String, conn: DbConn) -> Result<Json<Vec<Value>>, Error> {
+    if course != user.course {
+        return Err(Status::Forbidden.into());
+    }
+
+    let show_unreleased = course_role(&conn, &user.name, &course)? >= Role::Tutor;
+    let now = crate::tools::epoch();
+    let groups = course_groups(&conn, &course)?;
+    let mappings = courseTask::table.filter(courseTask::course.eq(&course))
+        .load::<Mapping>(&*conn)?;
+
+    groups.iter()
+        .filter(|group| show_unreleased || group.is_visible(now))
+        .map(|group| {
+            let tasks = mappings.iter()
+                .filter(|mapping| mapping.group_id == Some(group.id))
+                .filter(|mapping| show_unreleased || is_released(mapping, &groups, now))
+                .map(|mapping| mapping.taskid)
+                .collect::<BTreeSet<_>>();
+
+            let mut json = serde_json::to_value(group)?;
+            json["tasks"] = json!(tasks);
+            Ok(json)
+        })
+        .collect::<Result<Vec<_>, Error>>()
+        .map(Json)
+}
+
+/// Creates a group, body: {"title": "Sheet 1", "description": "...", "orderBy": 1,
+/// "visibleFrom": 1634000000, "passThreshold": 0.5}. Only the title is required.
+/// Tasks are assigned with the groupId of their course mapping.
+#[post("/admin/courses/<course>/groups", data = "<data>")]
+pub fn route_post_group(_admin: guards::Admin, course: String, data: Json<Value>, conn: DbConn) -> Result<Json<Value>, Error> {
+    if super::name_to_title(&conn, &course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+
+    let title = data["title"].as_str()
+        .ok_or(Status::BadRequest)?;
+    let pass_threshold = pass_threshold(data.get("passThreshold"))?.unwrap_or(1.0);
+
+    let id = conn.transaction::<_, Error, _>(|| {
+        diesel::insert_into(taskGroups::table)
+            .values((
+                taskGroups::course.eq(&course),
+                taskGroups::title.eq(title),
+                taskGroups::description.eq(data["description"].as_str().unwrap_or("")),
+                taskGroups::orderBy.eq(data["orderBy"].as_i64().unwrap_or(0) as i32),
+                taskGroups::visibleFrom.eq(data["visibleFrom"].as_i64()),
+                taskGroups::passThreshold.eq(pass_threshold)
+            ))
+            .execute(&*conn)?;
+
+        crate::tools::last_insert_id(&conn)
+    })?;
+
+    Ok(Json(json!({ "id": id })))
+}
+
+/// Updates all fields given in the body (see route_post_group),
+/// `"visibleFrom": null` releases the group immediately.
+#[patch("/admin/groups/<id>", data = "<data>")]
+pub fn route_patch_group(_admin: guards::Admin, id: i32, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let group = taskGroups::table.filter(taskGroups::id.eq(id))
+        .first::<TaskGroup>(&*conn)?;
+
+    let visible_from = match data.get("visibleFrom") {
+        Some(Value::Null) => None,
+        Some(visible_from) => Some(visible_from.as_i64().ok_or(Status::BadRequest)?),
+        None => group.visible_from
+    };
+
+    diesel::update(taskGroups::table.filter(taskGroups::id.eq(id)))
+        .set((
+            taskGroups::title.eq(data["title"].as_str().unwrap_or(&group.title)),
+            taskGroups::description.eq(data["description"].as_str().unwrap_or(&group.description)),
+            taskGroups::orderBy.eq(data["orderBy"].as_i64().map_or(group.order_by, |order_by| order_by as i32)),
+            taskGroups::visibleFrom.eq(visible_from),
+            taskGroups::passThreshold.eq(pass_threshold(data.get("passThreshold"))?.unwrap_or(group.pass_threshold))
+        ))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+/// Deletes an empty group. Groups with tasks are refused with 409, as removing
+/// the tasks from a group changes their versions.
+#[delete("/admin/groups/<id>")]
+pub fn route_delete_group(_admin: guards::Admin, id: i32, conn: DbConn) -> Result<Status, Error> {
+    let tasks = courseTask::table.filter(courseTask::groupId.eq(id))
+        .count()
+        .get_result::<i64>(&*conn)?;
+    if tasks > 0 {
+        return Err(Status::Conflict.into());
+    }
+
+    let deleted = diesel::delete(taskGroups::table.filter(taskGroups::id.eq(id)))
+        .execute(&*conn)?;
+    if deleted == 0 {
+        return Err(Status::NotFound.into());
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Groups of a course, ordered by orderBy.
+pub fn course_groups(conn: &MysqlConnection, course: &str) -> Result<Vec<TaskGroup>, Error> {
+    Ok(taskGroups::table.filter(taskGroups::course.eq(course))
+        .order((taskGroups::orderBy, taskGroups::id))
+        .load::<TaskGroup>(conn)?)
+}
+
+pub fn group_exists(conn: &MysqlConnection, course: &str, id: i32) -> Result<bool, Error> {
+    Ok(taskGroups::table.filter(taskGroups::id.eq(id))
+        .filter(taskGroups::course.eq(course))
+        .select(taskGroups::id)
+        .first::<i32>(conn)
+        .optional()?
+        .is_some())
+}
+
+/// A task is released when both the task and its group are visible.
+pub(crate) fn is_released(mapping: &Mapping, groups: &[TaskGroup], now: i64) -> bool {
+    let group_visible = mapping.group_id
+        .and_then(|id| groups.iter().find(|group| group.id == id))
+        .is_none_or(|group| group.is_visible(now));
+
+    group_visible && mapping.visible_from.is_none_or(|visible_from| visible_from <= now)
+}
+
+/// Completion of a group, e.g. {"id": 1, "title": "Sheet 1", "total": 4,
+/// "solved": 2, "solvedLate": 1, "passed": true}. Late solutions count for
+/// passing; empty groups are never passed.
+pub fn group_progress(group: &TaskGroup, taskids: &[i32], solved: &BTreeSet<i32>, solved_late: &BTreeSet<i32>) -> Value {
+    let count = |set: &BTreeSet<i32>| taskids.iter().filter(|id| set.contains(id)).count();
+    let (total, solved, solved_late) = (taskids.len(), count(solved), count(solved_late));
+
+    json!({
+        "id": group.id,
+        "title": group.title,
+        "total": total,
+        "solved": solved,
+        "solvedLate": solved_late,
+        "passed": total > 0 && (solved + solved_late) as f32 / total as f32 >= group.pass_threshold
+    })
+}
+
+fn pass_threshold(value: Option<&Value>) -> Result<Option<f32>, Error> {
+    match value {
+        None => Ok(None),
+        Some(value) => match value.as_f64() {
+            Some(threshold) if (0.0..=1.0).contains(&threshold) => Ok(Some(threshold as f32)),
+            _ => Err(Status::BadRequest.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_threshold() {
+        let group = TaskGroup {
+            id: 1,
+            course: "testbeans".to_string(),
+            title: "Sheet 1".to_string(),
+            description: String::new(),
+            order_by: 0,
+            visible_from: None,
+            pass_threshold: 0.5
+        };
+        let solved = vec![1].into_iter().collect();
+        let solved_late = vec![2].into_iter().collect();
+
+        let progress = group_progress(&group, &[1, 2, 3, 4], &solved, &solved_late);
+        assert_eq!(progress["solved"], 1);
+        assert_eq!(progress["solvedLate"], 1);
+        assert_eq!(progress["passed"], true);
+
+        assert_eq!(group_progress(&group, &[1, 3, 4], &solved, &solved_late)["passed"], false);
+        assert_eq!(group_progress(&group, &[], &solved, &solved_late)["passed"], false);
+    }
+}
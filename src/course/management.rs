@@ -0,0 +1,187 @@
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use serde_json::Value;
+use diesel::prelude::*;
+use crate::schema::{courses, courseMapping};
+use crate::auth::guards;
+use crate::DbConn;
+use crate::error::Error;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCourse {
+    pub name: String,
+    pub title: String,
+    pub config: Value,
+    pub archived: bool,
+    /// Key for self-enrollment, None if self-enrollment is disabled
+    pub enrollment_key: Option<String>,
+    /// Stud.IP context_ids (LTI launches) mapped to this course
+    pub mappings: Vec<String>
+}
+
+#[get("/admin/courses")]
+pub fn route_get_courses(_admin: guards::Admin, conn: DbConn) -> Result<Json<Vec<AdminCourse>>, Error> {
+    let mappings = courseMapping::table.select((courseMapping::studipId, courseMapping::courseName))
+        .load::<(String, String)>(&*conn)?;
+
+    let courses = courses::table.select((courses::name, courses::title, courses::config, courses::archived, courses::enrollmentKey))
+        .order(courses::name)
+        .load::<(String, String, String, bool, Option<String>)>(&*conn)?
+        .into_iter()
+        .map(|(name, title, config, archived, enrollment_key)| {
+            Ok(AdminCourse {
+                mappings: mappings.iter()
+                    .filter(|(_, course)| course == &name)
+                    .map(|(studip_id, _)| studip_id.clone())
+                    .collect(),
+                name,
+                title,
+                config: serde_json::from_str(&config)?,
+                archived,
+                enrollment_key
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Json(courses))
+}
+
+/// Creates a course, body: {"name": "...", "title": "...", "config": {...}, "enrollmentKey": "..."}.
+/// The config is optional and defaults to `{}`, without enrollment key there is no self-enrollment.
+#[post("/admin/courses", data = "<data>")]
+pub fn route_post_course(_admin: guards::Admin, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let name = data["name"].as_str()
+        .filter(|name| valid_course_name(name))
+        .ok_or(Status::BadRequest)?;
+    let title = data["title"].as_str()
+        .ok_or(Status::BadRequest)?;
+    let config = course_config(data.get("config"))?
+        .unwrap_or_else(|| json!({}));
+
+    if super::name_to_title(&conn, name)?.is_some() {
+        return Err(Status::Conflict.into());
+    }
+
+    diesel::insert_into(courses::table)
+        .values((
+            courses::name.eq(name),
+            courses::title.eq(title),
+            courses::config.eq(config.to_string()),
+            courses::enrollmentKey.eq(data["enrollmentKey"].as_str())
+        ))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+/// Updates all fields given in the body (title, config, archived, enrollmentKey).
+/// The config is replaced as a whole, `"enrollmentKey": null` disables self-enrollment.
+#[patch("/admin/courses/<course>", data = "<data>")]
+pub fn route_patch_course(_admin: guards::Admin, course: String, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let (title, config, archived, enrollment_key) = courses::table.filter(courses::name.eq(&course))
+        .select((courses::title, courses::config, courses::archived, courses::enrollmentKey))
+        .first::<(String, String, bool, Option<String>)>(&*conn)?;
+
+    let config = match course_config(data.get("config"))? {
+        Some(config) => config.to_string(),
+        None => config
+    };
+
+    let enrollment_key = match data.get("enrollmentKey") {
+        Some(Value::Null) => None,
+        Some(key) => Some(key.as_str().ok_or(Status::BadRequest)?.to_string()),
+        None => enrollment_key
+    };
+
+    // Rolled back if the prerequisite graph of the course is invalid
+    conn.transaction::<_, Error, _>(|| {
+        diesel::update(courses::table.filter(courses::name.eq(&course)))
+            .set((
+                courses::title.eq(data["title"].as_str().unwrap_or(&title)),
+                courses::config.eq(config),
+                courses::archived.eq(data["archived"].as_bool().unwrap_or(archived)),
+                courses::enrollmentKey.eq(enrollment_key)
+            ))
+            .execute(&*conn)?;
+
+        if data.get("config").is_some() {
+            super::prerequisites::check_course_graph(&conn, &course)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(Status::Ok)
+}
+
+/// Archives a course. Submissions and tasks are kept, but nobody can log in to
+/// the course anymore. Use PATCH with `"archived": false` to restore it.
+#[delete("/admin/courses/<course>")]
+pub fn route_delete_course(_admin: guards::Admin, course: String, conn: DbConn) -> Result<Status, Error> {
+    let updated = diesel::update(courses::table.filter(courses::name.eq(&course)))
+        .set(courses::archived.eq(true))
+        .execute(&*conn)?;
+
+    if updated == 0 {
+        return Err(Status::NotFound.into());
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Maps a Stud.IP context_id to a course, body: {"course": "..."}.
+/// An existing mapping of the context_id is replaced.
+#[put("/admin/courseMappings/<studip_id>", data = "<data>")]
+pub fn route_put_course_mapping(_admin: guards::Admin, studip_id: String, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
+    let course = data["course"].as_str()
+        .ok_or(Status::BadRequest)?;
+
+    if super::name_to_title(&conn, course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+
+    diesel::replace_into(courseMapping::table)
+        .values((
+            courseMapping::studipId.eq(&studip_id),
+            courseMapping::courseName.eq(course)
+        ))
+        .execute(&*conn)?;
+
+    Ok(Status::Ok)
+}
+
+#[delete("/admin/courseMappings/<studip_id>")]
+pub fn route_delete_course_mapping(_admin: guards::Admin, studip_id: String, conn: DbConn) -> Result<Status, Error> {
+    let deleted = diesel::delete(courseMapping::table.filter(courseMapping::studipId.eq(&studip_id)))
+        .execute(&*conn)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound.into());
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Returns true if the course exists and is archived.
+pub fn is_archived(conn: &MysqlConnection, course: &str) -> Result<bool, Error> {
+    Ok(courses::table.filter(courses::name.eq(course))
+        .select(courses::archived)
+        .first::<bool>(conn)
+        .optional()?
+        .unwrap_or(false))
+}
+
+/// Course names are part of URLs, so only allow letters, digits, '-' and '_'.
+fn valid_course_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 128
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Course configs have to be JSON objects.
+fn course_config(config: Option<&Value>) -> Result<Option<Value>, Error> {
+    match config {
+        None => Ok(None),
+        Some(config) if config.is_object() => Ok(Some(config.clone())),
+        Some(_) => Err(Status::BadRequest.into())
+    }
+}
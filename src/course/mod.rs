@@ -2,50 +2,101 @@ use diesel::prelude::*;
 use rocket::serde::json::Json;
 use rocket::http::Status;
 use serde_json::Value;
+use std::collections::BTreeSet;
 use crate::auth::guards;
+use crate::DbConn;
+use crate::error::Error;
 
 pub mod tasks;
 pub mod submissions;
+pub mod grading;
+pub mod management;
+pub mod enrollments;
+pub mod bundles;
+pub mod versions;
+pub mod prerequisites;
+pub mod groups;
 
 #[get("/courses/<course>/meta")]
-pub fn route_get_course_meta(course: String) -> Result<Json<Value>, Status> {
+pub fn route_get_course_meta(course: String, conn: DbConn) -> Result<Json<Value>, Error> {
     use crate::schema::courses;
     let (title, config) = courses::table.filter(courses::name.eq(&course))
         .select((courses::title, courses::config))
-        .first::<(String, String)>(&crate::database_connection())
-        .or(Err(Status::NotFound))?;
+        .first::<(String, String)>(&*conn)?;
 
     Ok(Json(json!({
         "name": course,
         "title": title,
-        "config": (serde_json::from_str(&config) as serde_json::Result<Value>).unwrap()
+        "config": serde_json::from_str::<Value>(&config)?
     })))
 }
 
+/// Progress of the user in the released tasks of a course, e.g.
+/// {"tasks": [{"taskid": 1, "bestScore": 1.0, "attempts": 3, "firstSolved": 1634000000,
+/// "status": "solved", "points": 2.0, "maxPoints": 2.0}, ...], "points": 2.0,
+/// "maxPoints": 4.0, "percentage": 50.0, "solved": [1], "solvedLate": [], "groups": [...]}.
+/// Points follow the grading config and count the same tasks as the course
+/// grade (see grading::graded_tasks), so partially solved tasks count in score
+/// mode. Tasks with a successful submission before the deadline count as
+/// solved, tasks that were only solved after the deadline as solvedLate.
+/// `groups` contains the completion of every released task group (see
+/// groups::group_progress).
 #[get("/courses/<course>/progress")]
-pub fn route_get_course_progress(user: guards::User, course: String) -> Result<Json<Vec<i32>>, Status> {
+pub fn route_get_course_progress(user: guards::User, course: String, conn: DbConn) -> Result<Json<Value>, Error> {
     if user.course != course {
-        return Err(Status::Forbidden);
+        return Err(Status::Forbidden.into());
     }
 
     use crate::schema::submissions;
-    let mut tasks = submissions::table.filter(submissions::course.eq(course))
-        .filter(submissions::user.eq(user.name))
-        .filter(submissions::resultType.eq("SUCCESS"))
-        .select(submissions::taskid)
-        .load::<i32>(&crate::database_connection())
-        .expect("Database error");
+    let submissions = submissions::table.filter(submissions::course.eq(&course))
+        .filter(submissions::user.eq(&user.name))
+        .select((submissions::taskid, submissions::resultType, submissions::score, submissions::late, submissions::timestamp))
+        .load::<(i32, String, f32, bool, i64)>(&*conn)?;
 
-    tasks.sort();
-    tasks.dedup();
+    let mappings = grading::graded_tasks(&conn, &course)?;
+    let config = grading::grading_config(&conn, &course)?;
+    let progress = mappings.iter()
+        .map(|mapping| config.task_progress(mapping.taskid, &submissions))
+        .collect::<Vec<_>>();
 
-    Ok(Json(tasks))
+    let taskids_with = |status: grading::TaskStatus| progress.iter()
+        .filter(|task| task.status == status)
+        .map(|task| task.taskid)
+        .collect::<BTreeSet<_>>();
+    let solved = taskids_with(grading::TaskStatus::Solved);
+    let solved_late = taskids_with(grading::TaskStatus::SolvedLate);
+
+    let now = crate::tools::epoch();
+    let groups = groups::course_groups(&conn, &course)?.iter()
+        .filter(|group| group.is_visible(now))
+        .map(|group| {
+            let taskids = mappings.iter()
+                .filter(|mapping| mapping.group_id == Some(group.id))
+                .map(|mapping| mapping.taskid)
+                .collect::<Vec<_>>();
+            groups::group_progress(group, &taskids, &solved, &solved_late)
+        })
+        .collect::<Vec<_>>();
+
+    let points = progress.iter().map(|task| task.points).sum::<f64>();
+    let max_points = progress.iter().map(|task| task.max_points).sum::<f64>();
+    let percentage = if max_points > 0.0 { 100.0 * points / max_points } else { 0.0 };
+
+    Ok(Json(json!({
+        "tasks": progress,
+        "points": points,
+        "maxPoints": max_points,
+        "percentage": percentage,
+        "solved": solved,
+        "solvedLate": solved_late,
+        "groups": groups
+    })))
 }
 
-pub fn name_to_title(course: &str) -> Option<String> {
+pub fn name_to_title(conn: &MysqlConnection, course: &str) -> Result<Option<String>, Error> {
     use crate::schema::courses;
-    courses::table.filter(courses::name.eq(&course))
+    Ok(courses::table.filter(courses::name.eq(&course))
         .select(courses::title)
-        .first::<String>(&crate::database_connection())
-        .ok()
+        .first::<String>(conn)
+        .optional()?)
 }
\ No newline at end of file
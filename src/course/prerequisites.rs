@@ -0,0 +1,262 @@
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::convert::TryFrom;
+use crate::auth::guards;
+use crate::schema::{courseTask, submissions};
+use crate::DbConn;
+use crate::error::Error;
+use super::tasks::Mapping;
+
+/// Prerequisites of a task in a course, stored as JSON in
+/// courseTask.prerequisites. Either a list of task ids that all have to be
+/// solved, or a boolean expression like `{"or": [1, {"and": [2, 3]}]}`.
+/// Lists inside of expressions are conjunctions as well.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prerequisite {
+    Task(i32),
+    All(Vec<Prerequisite>),
+    Any(Vec<Prerequisite>)
+}
+
+impl Prerequisite {
+    /// Parses the JSON format described above; null means no prerequisites.
+    pub fn parse(value: &Value) -> Result<Prerequisite, String> {
+        let list = |values: &[Value]| values.iter()
+            .map(Prerequisite::parse)
+            .collect::<Result<Vec<_>, _>>();
+
+        match value {
+            Value::Null => Ok(Prerequisite::All(Vec::new())),
+            Value::Number(number) => number.as_i64()
+                .and_then(|taskid| i32::try_from(taskid).ok())
+                .map(Prerequisite::Task)
+                .ok_or_else(|| format!("Invalid task id {}", number)),
+            Value::Array(values) => Ok(Prerequisite::All(list(values)?)),
+            Value::Object(object) if object.len() == 1 => {
+                let (operator, operands) = object.iter().next().expect("Object has one entry");
+                let operands = operands.as_array()
+                    .ok_or_else(|| format!("Operands of \"{}\" must be a list", operator))?;
+
+                match operator.as_str() {
+                    "and" => Ok(Prerequisite::All(list(operands)?)),
+                    "or" if operands.is_empty() => Err("\"or\" needs at least one operand".to_string()),
+                    "or" => Ok(Prerequisite::Any(list(operands)?)),
+                    _ => Err(format!("Unknown operator \"{}\"", operator))
+                }
+            },
+            _ => Err(format!("Invalid prerequisite {}", value))
+        }
+    }
+
+    pub fn is_met(&self, solved: &HashSet<i32>) -> bool {
+        match self {
+            Prerequisite::Task(taskid) => solved.contains(taskid),
+            Prerequisite::All(prerequisites) => prerequisites.iter().all(|p| p.is_met(solved)),
+            Prerequisite::Any(prerequisites) => prerequisites.iter().any(|p| p.is_met(solved))
+        }
+    }
+
+    /// All task ids the expression refers to
+    pub fn taskids(&self) -> BTreeSet<i32> {
+        match self {
+            Prerequisite::Task(taskid) => std::iter::once(*taskid).collect(),
+            Prerequisite::All(prerequisites) | Prerequisite::Any(prerequisites) => {
+                prerequisites.iter().flat_map(Prerequisite::taskids).collect()
+            }
+        }
+    }
+}
+
+/// Dependency graph of the tasks of a course: every task with the tasks its
+/// prerequisites refer to. Cycles are refused even if an "or" could break
+/// them, since they are almost always a mistake.
+#[derive(Debug, Default)]
+pub struct Graph {
+    requires: BTreeMap<i32, BTreeSet<i32>>
+}
+
+impl Graph {
+    pub fn new(tasks: impl IntoIterator<Item = (i32, Prerequisite)>) -> Graph {
+        Graph {
+            requires: tasks.into_iter()
+                .map(|(taskid, prerequisite)| (taskid, prerequisite.taskids()))
+                .collect()
+        }
+    }
+
+    /// Returns the tasks in an order in which every task comes after its
+    /// prerequisites, or a description of the first unknown task id or cycle.
+    pub fn topological_order(&self) -> Result<Vec<i32>, String> {
+        for (taskid, requires) in &self.requires {
+            if let Some(unknown) = requires.iter().find(|id| !self.requires.contains_key(id)) {
+                return Err(format!("Task {} requires task {}, which is not in the course", taskid, unknown));
+            }
+        }
+
+        let mut order = Vec::new();
+        let mut done = BTreeSet::new();
+        let mut remaining = self.requires.keys().copied().collect::<BTreeSet<_>>();
+        // Tasks with the lowest ids first, so the order is stable
+        while let Some(next) = remaining.iter().copied().find(|id| self.requires[id].is_subset(&done)) {
+            remaining.remove(&next);
+            done.insert(next);
+            order.push(next);
+        }
+
+        if let Some(start) = remaining.iter().next() {
+            // Every remaining task requires another remaining task, so following them leads into a cycle
+            let mut path = vec![*start];
+            loop {
+                let last = path[path.len() - 1];
+                let next = *self.requires[&last].iter()
+                    .find(|id| remaining.contains(id))
+                    .expect("Remaining tasks have a remaining prerequisite");
+                if let Some(position) = path.iter().position(|id| *id == next) {
+                    let cycle = path[position..].iter()
+                        .chain(std::iter::once(&next))
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>();
+                    return Err(format!("Cyclic prerequisites: {}", cycle.join(" -> ")));
+                }
+                path.push(next);
+            }
+        }
+
+        Ok(order)
+    }
+
+    fn to_json(&self) -> Value {
+        json!(self.requires.iter()
+            .map(|(taskid, requires)| json!({ "taskid": taskid, "requires": requires }))
+            .collect::<Vec<_>>())
+    }
+}
+
+/// Prerequisite graph of a course with a topological order of its tasks, e.g.
+/// {"tasks": [{"taskid": 2, "requires": [1]}, ...], "order": [1, 2], "error": null}.
+/// Graphs stored before validation existed may be invalid; then the order is
+/// null and error describes the problem.
+#[get("/admin/courses/<course>/prerequisites")]
+pub fn route_get_prerequisite_graph(_admin: guards::Admin, course: String, conn: DbConn) -> Result<Json<Value>, Error> {
+    if super::name_to_title(&conn, &course)?.is_none() {
+        return Err(rocket::http::Status::NotFound.into());
+    }
+
+    let graph = course_graph(&conn, &course)?;
+    let (order, error) = match graph.topological_order() {
+        Ok(order) => (Some(order), None),
+        Err(err) => (None, Some(err))
+    };
+    Ok(Json(json!({
+        "tasks": graph.to_json(),
+        "order": order,
+        "error": error
+    })))
+}
+
+/// Validates the prerequisite graphs of all courses the given tasks are or
+/// will be mapped to, as if the mappings of these tasks were replaced by the
+/// given ones. Meant to be called before the mappings are stored.
+pub(crate) fn check_graphs(conn: &MysqlConnection, changes: &[(i32, &[Mapping])]) -> Result<(), Error> {
+    let taskids = changes.iter().map(|(taskid, _)| *taskid).collect::<BTreeSet<_>>();
+    let new_mappings = changes.iter().flat_map(|(_, mappings)| mappings.iter());
+
+    let mut courses = courseTask::table.filter(courseTask::taskid.eq_any(&taskids))
+        .select(courseTask::course)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect::<BTreeSet<_>>();
+    courses.extend(new_mappings.clone().map(|mapping| mapping.course.clone()));
+
+    let mut by_course = BTreeMap::<String, Vec<Mapping>>::new();
+    let existing = courseTask::table.filter(courseTask::course.eq_any(&courses))
+        .load::<Mapping>(conn)?
+        .into_iter()
+        .filter(|mapping| !taskids.contains(&mapping.taskid));
+    for mapping in existing.chain(new_mappings.cloned()) {
+        by_course.entry(mapping.course.clone()).or_default().push(mapping);
+    }
+
+    for (course, mappings) in by_course {
+        Graph::new(parse_all(&mappings)?)
+            .topological_order()
+            .map_err(|err| Error::Prerequisites(format!("Course {}: {}", course, err)))?;
+    }
+
+    Ok(())
+}
+
+/// Validates the current prerequisite graph of a course.
+pub fn check_course_graph(conn: &MysqlConnection, course: &str) -> Result<(), Error> {
+    course_graph(conn, course)?
+        .topological_order()
+        .map_err(|err| Error::Prerequisites(format!("Course {}: {}", course, err)))?;
+    Ok(())
+}
+
+fn course_graph(conn: &MysqlConnection, course: &str) -> Result<Graph, Error> {
+    let mappings = courseTask::table.filter(courseTask::course.eq(course))
+        .load::<Mapping>(conn)?;
+    Ok(Graph::new(parse_all(&mappings)?))
+}
+
+fn parse_all(mappings: &[Mapping]) -> Result<Vec<(i32, Prerequisite)>, Error> {
+    mappings.iter()
+        .map(|mapping| Ok((mapping.taskid, mapping.parsed_prerequisites()?)))
+        .collect()
+}
+
+/// Ids of all tasks the user has a successful submission for in the course.
+pub fn solved_tasks(conn: &MysqlConnection, user: &str, course: &str) -> Result<HashSet<i32>, Error> {
+    Ok(submissions::table.filter(submissions::course.eq(course))
+        .filter(submissions::user.eq(user))
+        .filter(submissions::resultType.eq("SUCCESS"))
+        .select(submissions::taskid)
+        .distinct()
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expressions() {
+        let solved = vec![1, 3].into_iter().collect::<HashSet<_>>();
+        let met = |value: Value| Prerequisite::parse(&value).unwrap().is_met(&solved);
+
+        assert!(met(json!(null)));
+        assert!(met(json!([])));
+        assert!(met(json!([1, 3])));
+        assert!(!met(json!([1, 2])));
+        assert!(met(json!({ "or": [2, [1, 3]] })));
+        assert!(!met(json!({ "or": [2, { "and": [1, 4] }] })));
+
+        assert_eq!(Prerequisite::parse(&json!({ "or": [2, { "and": [1, 4] }] })).unwrap().taskids(),
+            vec![1, 2, 4].into_iter().collect());
+
+        assert!(Prerequisite::parse(&json!({ "xor": [1, 2] })).is_err());
+        assert!(Prerequisite::parse(&json!({ "or": [] })).is_err());
+        assert!(Prerequisite::parse(&json!(["1"])).is_err());
+        assert!(Prerequisite::parse(&json!(1.5)).is_err());
+    }
+
+    #[test]
+    fn graph() {
+        let graph = |tasks: Vec<(i32, Value)>| {
+            Graph::new(tasks.into_iter().map(|(taskid, value)| (taskid, Prerequisite::parse(&value).unwrap())))
+                .topological_order()
+        };
+
+        assert_eq!(graph(vec![(1, json!([3])), (2, json!({ "or": [1, 3] })), (3, json!([]))]), Ok(vec![3, 1, 2]));
+        assert_eq!(graph(vec![(1, json!([4])), (2, json!([]))]),
+            Err("Task 1 requires task 4, which is not in the course".to_string()));
+        assert_eq!(graph(vec![(1, json!([])), (2, json!([1, 4])), (3, json!([2])), (4, json!({ "or": [3] }))]),
+            Err("Cyclic prerequisites: 2 -> 4 -> 3 -> 2".to_string()));
+        assert_eq!(graph(vec![(1, json!([1]))]), Err("Cyclic prerequisites: 1 -> 1".to_string()));
+    }
+}
@@ -2,28 +2,34 @@ use diesel::prelude::*;
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use serde_json::Value;
-use rand::seq::SliceRandom;
 use crate::auth::guards;
 use crate::schema::submissions;
-use crate::SETTINGS;
-use reqwest::header::CONTENT_TYPE;
+use crate::{SETTINGS, DbConn, DbPool};
+use crate::error::Error;
+use rocket::State;
+use rocket::tokio::time::{self, Duration, Instant};
+use crate::evaluation::{Queue, EVALUATING, PENDING};
+use crate::events::{CourseEvent, Envelope, Hub};
+use crate::auth::roles::{course_role, Role};
+use super::prerequisites::solved_tasks;
+use super::tasks::Mapping;
 
 #[get("/courses/<course>/tasks/all/submissions", rank = 1)]
-pub fn route_get_all_submissions(user: guards::User, course: String) -> Result<Json<Vec<PublicSubmission>>, Status> {
+pub fn route_get_all_submissions(user: guards::User, course: String, conn: DbConn) -> Result<Json<Vec<PublicSubmission>>, Error> {
     if course != user.course {
-        return Err(Status::Forbidden);
+        return Err(Status::Forbidden.into());
     }
 
-    Ok(Json(get_public_submissions(&user.name, &course)))
+    Ok(Json(get_public_submissions(&conn, &user.name, &course)?))
 }
 
 #[get("/courses/<course>/tasks/<taskid>/submissions", rank = 2)]
-pub fn route_get_task_submissions(user: guards::User, course: String, taskid: i32) -> Result<Json<Vec<PublicSubmission>>, Status> {
+pub fn route_get_task_submissions(user: guards::User, course: String, taskid: i32, conn: DbConn) -> Result<Json<Vec<PublicSubmission>>, Error> {
     if course != user.course {
-        return Err(Status::Forbidden);
+        return Err(Status::Forbidden.into());
     }
 
-    let submissions = get_public_submissions(&user.name, &course)
+    let submissions = get_public_submissions(&conn, &user.name, &course)?
         .into_iter()
         .filter(|sub| sub.taskid == taskid)
         .collect::<Vec<_>>();
@@ -32,59 +38,106 @@ pub fn route_get_task_submissions(user: guards::User, course: String, taskid: i3
 }
 
 #[get("/courses/<course>/tasks/<taskid>/submissions/<submissionid>")]
-pub fn route_get_single_submission(user: guards::User, course: String, taskid: i32, submissionid: i32) -> Result<Json<PublicSubmission>, Status> {
+pub fn route_get_single_submission(user: guards::User, course: String, taskid: i32, submissionid: i32, conn: DbConn) -> Result<Json<PublicSubmission>, Error> {
     if course != user.course {
-        return Err(Status::Forbidden);
+        return Err(Status::Forbidden.into());
     }
 
-    let submission = get_public_submissions(&user.name, &course)
+    let submission = get_public_submissions(&conn, &user.name, &course)?
         .into_iter()
         .filter(|sub| sub.taskid == taskid)
-        .filter(|sub| sub.id == submissionid)
-        .next();
+        .find(|sub| sub.id == submissionid);
 
     Ok(Json(submission.ok_or(Status::NotFound)?))
 }
 
+/// Stores a submission with result type PENDING and queues it for evaluation.
+/// The result can be fetched with the result route below. Submissions of
+/// students can be refused or marked late (see check_submission).
 #[post("/courses/<course>/tasks/<taskid>/submissions", data = "<data>")]
-pub async fn route_post_submission(user: guards::User, course: String, taskid: i32, data: Json<Value>) -> Result<Json<Value>, Status> {
+pub fn route_post_submission(user: guards::User, course: String, taskid: i32, data: Json<Value>, conn: DbConn, queue: &State<Queue>, hub: &State<Hub>) -> Result<Json<Value>, Error> {
     if course != user.course {
-        return Err(Status::Forbidden);
+        return Err(Status::Forbidden.into());
     }
 
     let submission = data["submission"].as_str()
         .ok_or(Status::BadRequest)?;
 
-    use crate::schema::tasks;
-    let (lang, tests) = tasks::table.filter(tasks::taskid.eq(taskid))
-        .select((tasks::lang, tasks::tests))
-        .first::<(String, String)>(&crate::database_connection())
-        .expect("Database error");
-
-    let result = submit_solution(taskid,&lang, &serde_json::from_str(&tests).unwrap(), submission).await;
-
-    use crate::schema::submissions;
-    diesel::insert_into(submissions::table)
-        .values((
-            submissions::user.eq(user.name),
-            submissions::course.eq(course),
-            submissions::taskid.eq(taskid),
-            submissions::timestamp.eq(crate::tools::epoch()),
-            submissions::content.eq(submission),
-            submissions::resultType.eq(result["type"].as_str().unwrap()),
-            submissions::simplified.eq(serde_json::to_string(&result["simplified"]).unwrap()),
-            submissions::details.eq(serde_json::to_string(&result["details"]).unwrap()),
-            submissions::score.eq(result["score"].as_f64().unwrap() as f32)
-        ))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+    use crate::schema::courseTask;
+    let mapping = courseTask::table.filter(courseTask::course.eq(&course))
+        .filter(courseTask::taskid.eq(taskid))
+        .first::<Mapping>(&*conn)?;
+    let late = check_submission(&conn, &user.name, &course, &mapping)?;
+
+    let id = conn.transaction::<_, Error, _>(|| {
+        diesel::insert_into(submissions::table)
+            .values((
+                submissions::user.eq(&user.name),
+                submissions::course.eq(&course),
+                submissions::taskid.eq(taskid),
+                submissions::timestamp.eq(crate::tools::epoch()),
+                submissions::content.eq(submission),
+                submissions::resultType.eq(PENDING),
+                submissions::simplified.eq("null"),
+                submissions::details.eq("null"),
+                submissions::score.eq(0.0),
+                submissions::late.eq(late)
+            ))
+            .execute(&*conn)?;
+
+        crate::tools::last_insert_id(&conn)
+    })?;
+
+    queue.push(id);
+    hub.send_to_user(&user.name, &course, CourseEvent::SubmissionQueued { id, taskid });
 
     Ok(Json(json!({
-        "type": result["type"].as_str().unwrap(),
-        "score": result["score"].as_f64().unwrap()
+        "id": id,
+        "type": PENDING,
+        "late": late
     })))
 }
 
+/// Returns the submission as soon as it is evaluated, but waits at most
+/// evaluation.result_timeout seconds. Clients should request it again while
+/// the result type is still PENDING or EVALUATING. Database connections are
+/// only held for the queries, not while waiting.
+#[get("/courses/<course>/tasks/<taskid>/submissions/<submissionid>/result")]
+pub async fn route_get_submission_result(user: guards::User, course: String, taskid: i32, submissionid: i32, pool: &State<DbPool>, hub: &State<Hub>) -> Result<Json<PublicSubmission>, Error> {
+    if course != user.course {
+        return Err(Status::Forbidden.into());
+    }
+
+    let timeout = SETTINGS.get::<u64>("evaluation.result_timeout")
+        .expect("evaluation.result_timeout missing in settings");
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    // Subscribe first, so the evaluation can't finish unnoticed between the query and the subscription
+    let mut events = hub.subscribe();
+    loop {
+        let submission = get_public_submissions(&*DbConn::get(pool).await?, &user.name, &course)?
+            .into_iter()
+            .filter(|sub| sub.taskid == taskid)
+            .find(|sub| sub.id == submissionid)
+            .ok_or(Status::NotFound)?;
+
+        if submission.result_type != PENDING && submission.result_type != EVALUATING {
+            return Ok(Json(submission));
+        }
+
+        loop {
+            match time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Ok(Json(submission)),
+                Ok(Ok(Envelope { event: CourseEvent::SubmissionEvaluated { id, .. }, .. })) if id == submissionid => break,
+                Ok(Ok(_)) => continue,
+                // We missed some events and have to check the database
+                Ok(Err(_)) => break
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Queryable)]
 struct Submission {
     id: i32,
@@ -96,7 +149,9 @@ struct Submission {
     result_type: String,
     simplified: String,
     details: String,
-    score: f32
+    score: f32,
+    task_version: Option<i32>,
+    late: bool
 }
 
 #[derive(Serialize)]
@@ -107,51 +162,68 @@ pub struct PublicSubmission {
     content: String,
     result_type: String,
     simplified: Value,
-    score: f32
+    score: f32,
+    /// Version of the task the submission was evaluated against
+    task_version: Option<i32>,
+    /// Submitted after the deadline
+    late: bool
 }
 
-fn get_public_submissions(user: &str, course: &str) -> Vec<PublicSubmission> {
+/// Students can only submit to released (see groups::is_released) and unlocked
+/// tasks (see is_locked), and not after the late period. Returns whether the
+/// submission is late, i.e. after the deadline. Tutors and above can always submit.
+fn check_submission(conn: &MysqlConnection, user: &str, course: &str, mapping: &Mapping) -> Result<bool, Error> {
+    if course_role(conn, user, course)? >= Role::Tutor {
+        return Ok(false);
+    }
+
+    let now = crate::tools::epoch();
+    if !super::groups::is_released(mapping, &super::groups::course_groups(conn, course)?, now) {
+        return Err(Status::NotFound.into());
+    }
+    if is_locked(conn, user, course, mapping)? {
+        return Err(Error::TaskLocked);
+    }
+
+    match (mapping.deadline, mapping.late_until) {
+        (None, _) => Ok(false),
+        (Some(deadline), _) if now <= deadline => Ok(false),
+        (Some(_), Some(late_until)) if now <= late_until => Ok(true),
+        (Some(_), _) => Err(Error::DeadlinePassed)
+    }
+}
+
+/// Tasks with unsolved prerequisites are locked, unless the course config
+/// contains `"allowLockedSubmissions": true`.
+fn is_locked(conn: &MysqlConnection, user: &str, course: &str, mapping: &Mapping) -> Result<bool, Error> {
+    if mapping.parsed_prerequisites()?.is_met(&solved_tasks(conn, user, course)?) {
+        return Ok(false);
+    }
+
+    use crate::schema::courses;
+    let config = courses::table.filter(courses::name.eq(course))
+        .select(courses::config)
+        .first::<String>(conn)?;
+    Ok(!serde_json::from_str::<Value>(&config)?["allowLockedSubmissions"].as_bool().unwrap_or(false))
+}
+
+fn get_public_submissions(conn: &MysqlConnection, user: &str, course: &str) -> Result<Vec<PublicSubmission>, Error> {
     submissions::table.filter(submissions::user.eq(user))
         .filter(submissions::course.eq(course))
-        .load::<Submission>(&crate::database_connection())
-        .expect("Database error")
+        .load::<Submission>(conn)?
         .into_iter()
         .map(|sub| {
-            PublicSubmission {
+            Ok(PublicSubmission {
                 id: sub.id,
                 taskid: sub.taskid,
                 timestamp: sub.timestamp,
                 content: sub.content,
                 result_type: sub.result_type,
-                simplified: serde_json::from_str(&sub.simplified).unwrap(),
-                score: sub.score
-            }
+                simplified: serde_json::from_str(&sub.simplified)?,
+                score: sub.score,
+                task_version: sub.task_version,
+                late: sub.late
+            })
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
-
-pub async fn submit_solution(taskid: i32, lang: &str, tests: &Value, submission: &str) -> Value {
-    let sandbox = SETTINGS.get::<Vec<String>>("sandbox.urls")
-        .expect("sandbox.urls missing in settings")
-        .choose(&mut rand::thread_rng())
-        .unwrap()
-        .to_string();
-
-    let body = json!({
-        "taskid": taskid,
-        "submission": submission,
-        "lang": lang,
-        "tests": tests
-    });
-
-    reqwest::Client::new()
-        .post(&format!("{}/evaluate", sandbox))
-        .header(CONTENT_TYPE, "application/json")
-        .json(&body)
-        .send()
-        .await
-        .unwrap()
-        .json()
-        .await
-        .unwrap()
-}
\ No newline at end of file
@@ -3,97 +3,410 @@ use std::collections::HashMap;
 use rocket::serde::json::Json;
 use serde_json::Value;
 use rocket::http::Status;
-use crate::course::name_to_title;
 use crate::auth::guards;
-use crate::schema::{tasks, courseTask};
+use crate::schema::{tasks, courseTask, taskVersions};
+use crate::DbConn;
+use crate::error::Error;
+use crate::evaluation::Queue;
+use rocket::State;
+use super::prerequisites::{self, Prerequisite};
+use super::groups;
+use crate::auth::roles::{course_role, Role};
 
 #[get("/courses/<course>/tasks")]
-pub fn route_get_tasks(course: String) -> Result<Json<Vec<PublicTask>>, Status> {
-    if name_to_title(&course).is_none() {
-        return Err(Status::NotFound);
+pub fn route_get_tasks(user: guards::User, course: String, conn: DbConn) -> Result<Json<Vec<PublicTask>>, Error> {
+    if course != user.course {
+        return Err(Status::Forbidden.into());
     }
 
-    Ok(Json(get_course_tasks(&course)))
+    Ok(Json(get_course_tasks(&conn, &course, &user.name)?))
 }
 
 #[get("/courses/<course>/tasks/<taskid>")]
-pub fn route_get_single_task(course: String, taskid: i32) -> Result<Json<PublicTask>, Status> {
-    if name_to_title(&course).is_none() {
-        return Err(Status::NotFound);
+pub fn route_get_single_task(user: guards::User, course: String, taskid: i32, conn: DbConn) -> Result<Json<PublicTask>, Error> {
+    if course != user.course {
+        return Err(Status::Forbidden.into());
     }
 
-    let task = get_course_tasks(&course).into_iter()
-        .filter(|task| task.taskid == taskid)
-        .next()
+    let task = get_course_tasks(&conn, &course, &user.name)?.into_iter()
+        .find(|task| task.taskid == taskid)
         .ok_or(Status::NotFound)?;
 
     Ok(Json(task))
 }
 
+/// Imports a task. The reference solution is evaluated first and the task is
+/// refused with 422 if it doesn't succeed; the response contains the
+/// evaluation result in both cases. `"validateSolution": false` skips the
+/// check, e.g. for tasks with deliberately failing examples.
+/// The task is stored as new version (see save_task).
 #[post("/task", data = "<data>")]
-pub fn route_post_task(_key: guards::AdminKey, data: Json<Value>) -> Result<Status, Status> {
+pub async fn route_post_task(admin: guards::Admin, data: Json<Value>, conn: DbConn, queue: &State<Queue>) -> Result<(Status, Json<Value>), Error> {
     let task = Task {
         taskid: data["taskid"].as_i64().ok_or(Status::BadRequest)? as i32,
-        task_description: serde_json::to_string(&data["taskDescription"]).unwrap(),
+        task_description: serde_json::to_string(&data["taskDescription"])?,
         solution: data["solution"].as_str().ok_or(Status::BadRequest)?.to_string(),
         lang: data["lang"].as_str().ok_or(Status::BadRequest)?.to_string(),
-        tests: serde_json::to_string(&data["tests"]).unwrap()
+        tests: serde_json::to_string(&data["tests"])?,
+        version: 0
     };
 
     let meta  = data["courseMetaData"].as_array()
         .ok_or(Status::BadRequest)?
-        .into_iter()
-        .map(|val| {
-            Ok(Mapping {
-                course: val["courseName"].as_str().ok_or(Status::BadRequest)?.to_string(),
-                taskid: data["taskid"].as_i64().ok_or(Status::BadRequest)? as i32,
-                tags: serde_json::to_string(&val["tags"]).unwrap(),
-                order_by: val["orderBy"].as_i64().ok_or(Status::BadRequest)? as i32,
-                prerequisites: serde_json::to_string(&val["prerequisites"]).unwrap(),
-            }) as Result<Mapping, Status>
-        })
+        .iter()
+        .map(|val| Mapping::from_json(task.taskid, val))
+        .collect::<Result<Vec<_>, _>>()?;
+    prerequisites::check_graphs(&conn, &[(task.taskid, &meta)])?;
+
+    let validation = match validate_solution(queue, &data, task.taskid, &task.lang, &data["tests"], &task.solution).await? {
+        Ok(validation) => validation,
+        Err(failed) => return Ok(failed)
+    };
+
+    let version = save_task(&conn, &task, &meta, &admin.name)?;
+
+    Ok((Status::Ok, Json(json!({ "version": version, "validation": validation }))))
+}
+
+#[get("/admin/tasks")]
+pub fn route_get_admin_tasks(_admin: guards::Admin, conn: DbConn) -> Result<Json<Vec<AdminTask>>, Error> {
+    let mappings = courseTask::table.load::<Mapping>(&*conn)?;
+
+    let tasks = get_all_tasks(&conn)?.into_iter()
+        .map(|task| AdminTask::new(task, &mappings))
         .collect::<Result<Vec<_>, _>>()?;
 
-    diesel::delete(tasks::table.filter(tasks::taskid.eq(task.taskid)))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+    Ok(Json(tasks))
+}
 
-    diesel::delete(courseTask::table.filter(courseTask::taskid.eq(task.taskid)))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+#[get("/admin/tasks/<taskid>")]
+pub fn route_get_admin_task(_admin: guards::Admin, taskid: i32, conn: DbConn) -> Result<Json<AdminTask>, Error> {
+    let task = tasks::table.filter(tasks::taskid.eq(taskid))
+        .first::<Task>(&*conn)?;
+    let mappings = courseTask::table.filter(courseTask::taskid.eq(taskid))
+        .load::<Mapping>(&*conn)?;
 
-    diesel::insert_into(tasks::table)
-        .values(task)
-        .execute(&crate::database_connection())
-        .expect("Database error");
+    Ok(Json(AdminTask::new(task, &mappings)?))
+}
 
-    diesel::insert_into(courseTask::table)
-        .values(meta)
-        .execute(&crate::database_connection())
-        .expect("Database error");
+/// Updates all fields given in the body (taskDescription, solution, lang, tests).
+/// Changes of solution, lang or tests are validated like in route_post_task.
+#[patch("/admin/tasks/<taskid>", data = "<data>")]
+pub async fn route_patch_task(admin: guards::Admin, taskid: i32, data: Json<Value>, conn: DbConn, queue: &State<Queue>) -> Result<(Status, Json<Value>), Error> {
+    let mut task = tasks::table.filter(tasks::taskid.eq(taskid))
+        .first::<Task>(&*conn)?;
+
+    if let Some(description) = data.get("taskDescription") {
+        task.task_description = serde_json::to_string(description)?;
+    }
+    if let Some(solution) = data.get("solution") {
+        task.solution = solution.as_str().ok_or(Status::BadRequest)?.to_string();
+    }
+    if let Some(lang) = data.get("lang") {
+        task.lang = lang.as_str().ok_or(Status::BadRequest)?.to_string();
+    }
+    if let Some(tests) = data.get("tests") {
+        task.tests = serde_json::to_string(tests)?;
+    }
+
+    let validation = if ["solution", "lang", "tests"].iter().any(|key| data.get(key).is_some()) {
+        let tests = serde_json::from_str::<Value>(&task.tests)?;
+        match validate_solution(queue, &data, task.taskid, &task.lang, &tests, &task.solution).await? {
+            Ok(validation) => validation,
+            Err(failed) => return Ok(failed)
+        }
+    }
+    else {
+        Value::Null
+    };
+
+    let mappings = courseTask::table.filter(courseTask::taskid.eq(taskid))
+        .load::<Mapping>(&*conn)?;
+    let version = save_task(&conn, &task, &mappings, &admin.name)?;
+
+    Ok((Status::Ok, Json(json!({ "version": version, "validation": validation }))))
+}
+
+/// Deletes a task and removes it from all courses. Submissions and versions
+/// are kept, so the task can be restored by a rollback.
+#[delete("/admin/tasks/<taskid>")]
+pub fn route_delete_task(_admin: guards::Admin, taskid: i32, conn: DbConn) -> Result<Status, Error> {
+    // Fails if other tasks require this one
+    prerequisites::check_graphs(&conn, &[(taskid, &[])])?;
+
+    let deleted = conn.transaction::<_, Error, _>(|| {
+        diesel::delete(courseTask::table.filter(courseTask::taskid.eq(taskid)))
+            .execute(&*conn)?;
+
+        Ok(diesel::delete(tasks::table.filter(tasks::taskid.eq(taskid)))
+            .execute(&*conn)?)
+    })?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound.into());
+    }
 
     Ok(Status::Ok)
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
-struct Task {
-    taskid: i32,
+/// Adds a task to a course or updates its mapping, body: {"tags": [...], "orderBy": 1, "prerequisites": [...],
+/// "visibleFrom": ..., "deadline": ..., "lateUntil": ...} (see Mapping::from_json).
+/// Mappings are part of the task versions, so this creates a new version.
+#[put("/admin/courses/<course>/tasks/<taskid>", data = "<data>")]
+pub fn route_put_course_task(admin: guards::Admin, course: String, taskid: i32, data: Json<Value>, conn: DbConn) -> Result<Json<Value>, Error> {
+    if crate::course::name_to_title(&conn, &course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+    let task = tasks::table.filter(tasks::taskid.eq(taskid))
+        .first::<Task>(&*conn)?;
+
+    let mut data = data.into_inner();
+    data.as_object_mut()
+        .ok_or(Status::BadRequest)?
+        .insert("courseName".to_string(), Value::String(course));
+    let mapping = Mapping::from_json(taskid, &data)?;
+
+    let mut mappings = courseTask::table.filter(courseTask::taskid.eq(taskid))
+        .filter(courseTask::course.ne(&mapping.course))
+        .load::<Mapping>(&*conn)?;
+    mappings.push(mapping);
+    prerequisites::check_graphs(&conn, &[(taskid, &mappings)])?;
+    let version = save_task(&conn, &task, &mappings, &admin.name)?;
+
+    Ok(Json(json!({ "version": version })))
+}
+
+#[delete("/admin/courses/<course>/tasks/<taskid>")]
+pub fn route_delete_course_task(admin: guards::Admin, course: String, taskid: i32, conn: DbConn) -> Result<Json<Value>, Error> {
+    let task = tasks::table.filter(tasks::taskid.eq(taskid))
+        .first::<Task>(&*conn)?;
+    let (removed, mappings) = courseTask::table.filter(courseTask::taskid.eq(taskid))
+        .load::<Mapping>(&*conn)?
+        .into_iter()
+        .partition::<Vec<_>, _>(|mapping| mapping.course == course);
+
+    if removed.is_empty() {
+        return Err(Status::NotFound.into());
+    }
+    prerequisites::check_graphs(&conn, &[(taskid, &mappings)])?;
+    let version = save_task(&conn, &task, &mappings, &admin.name)?;
+
+    Ok(Json(json!({ "version": version })))
+}
+
+/// Stores a task with its course mappings (replacing all existing ones) as
+/// new version and returns the version number. Every version is a snapshot
+/// of both, so it can be restored as a whole. Invalid prerequisites and groups
+/// of other courses are refused, but the prerequisite graphs have to be
+/// checked by the caller (see prerequisites::check_graphs), as imports change
+/// several tasks at once.
+pub(crate) fn save_task(conn: &MysqlConnection, task: &Task, mappings: &[Mapping], author: &str) -> Result<i32, Error> {
+    use diesel::expression::dsl::max;
+
+    for mapping in mappings {
+        mapping.parsed_prerequisites()?;
+        mapping.check_times()?;
+        if let Some(group) = mapping.group_id {
+            if !groups::group_exists(conn, &mapping.course, group)? {
+                return Err(Status::BadRequest.into());
+            }
+        }
+    }
+
+    conn.transaction(|| {
+        let version = taskVersions::table.filter(taskVersions::taskid.eq(task.taskid))
+            .select(max(taskVersions::version))
+            .first::<Option<i32>>(conn)?
+            .unwrap_or(0) + 1;
+
+        diesel::replace_into(tasks::table)
+            .values(&Task { version, ..task.clone() })
+            .execute(conn)?;
+        diesel::delete(courseTask::table.filter(courseTask::taskid.eq(task.taskid)))
+            .execute(conn)?;
+        if !mappings.is_empty() {
+            diesel::insert_into(courseTask::table)
+                .values(mappings)
+                .execute(conn)?;
+        }
+
+        let courses = mappings.iter()
+            .map(Mapping::to_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        diesel::insert_into(taskVersions::table)
+            .values((
+                taskVersions::taskid.eq(task.taskid),
+                taskVersions::version.eq(version),
+                taskVersions::taskDescription.eq(&task.task_description),
+                taskVersions::solution.eq(&task.solution),
+                taskVersions::lang.eq(&task.lang),
+                taskVersions::tests.eq(&task.tests),
+                taskVersions::courses.eq(serde_json::to_string(&courses)?),
+                taskVersions::author.eq(author),
+                taskVersions::timestamp.eq(crate::tools::epoch())
+            ))
+            .execute(conn)?;
+
+        Ok(version)
+    })
+}
+
+/// Evaluates the reference solution unless the request body contains
+/// `"validateSolution": false`. Returns the evaluation result (null if
+/// skipped), or the 422 response if the solution doesn't succeed.
+async fn validate_solution(queue: &Queue, data: &Value, taskid: i32, lang: &str, tests: &Value, solution: &str) -> Result<Result<Value, (Status, Json<Value>)>, Error> {
+    if !data["validateSolution"].as_bool().unwrap_or(true) {
+        return Ok(Ok(Value::Null));
+    }
+
+    let result = queue.evaluator()
+        .evaluate(taskid, lang, tests, solution)
+        .await?;
+    let result = json!({
+        "type": result.result_type,
+        "score": result.score,
+        "simplified": result.simplified,
+        "details": result.details
+    });
+
+    if result["type"] != "SUCCESS" {
+        return Ok(Err((Status::UnprocessableEntity, Json(json!({
+            "code": "solution_failed",
+            "message": "The reference solution does not pass the tests",
+            "validation": result
+        })))));
+    }
+
+    Ok(Ok(result))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Insertable)]
+pub(crate) struct Task {
+    pub taskid: i32,
     #[column_name = "taskDescription"]
-    task_description: String,
-    solution: String,
-    lang: String,
-    tests: String
+    pub task_description: String,
+    pub solution: String,
+    pub lang: String,
+    pub tests: String,
+    /// Current version, set by save_task
+    pub version: i32
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "courseTask"]
-struct Mapping {
-    course: String,
-    taskid: i32,
-    tags: String,
+pub(crate) struct Mapping {
+    pub course: String,
+    pub taskid: i32,
+    pub tags: String,
     #[column_name = "orderBy"]
-    order_by: i32,
-    prerequisites: String
+    pub order_by: i32,
+    pub prerequisites: String,
+    /// Students can't see the task before this time
+    #[column_name = "visibleFrom"]
+    pub visible_from: Option<i64>,
+    /// Later submissions are marked as late, or refused without late_until
+    pub deadline: Option<i64>,
+    /// Late submissions are refused after this time
+    #[column_name = "lateUntil"]
+    pub late_until: Option<i64>,
+    /// Task group (e.g. exercise sheet) of the course
+    #[column_name = "groupId"]
+    pub group_id: Option<i32>
+}
+
+impl Mapping {
+    /// Mapping in the format of the task routes:
+    /// {"courseName": "...", "tags": [...], "orderBy": 1, "prerequisites": [...],
+    /// "visibleFrom": 1634000000, "deadline": 1635000000, "lateUntil": null, "groupId": 3}
+    /// The timestamps and the group are optional.
+    pub fn from_json(taskid: i32, value: &Value) -> Result<Mapping, Error> {
+        Ok(Mapping {
+            course: value["courseName"].as_str().ok_or(Status::BadRequest)?.to_string(),
+            taskid,
+            tags: serde_json::to_string(&value["tags"])?,
+            order_by: value["orderBy"].as_i64().ok_or(Status::BadRequest)? as i32,
+            prerequisites: serde_json::to_string(&value["prerequisites"])?,
+            visible_from: optional_timestamp(&value["visibleFrom"])?,
+            deadline: optional_timestamp(&value["deadline"])?,
+            late_until: optional_timestamp(&value["lateUntil"])?,
+            group_id: match &value["groupId"] {
+                Value::Null => None,
+                group => Some(group.as_i64().ok_or(Status::BadRequest)? as i32)
+            }
+        })
+    }
+
+    /// A late period needs a deadline, and the task must be visible before both.
+    pub fn check_times(&self) -> Result<(), Error> {
+        let valid = match (self.visible_from, self.deadline, self.late_until) {
+            (_, None, Some(_)) => false,
+            (_, Some(deadline), Some(late_until)) if late_until < deadline => false,
+            (Some(visible_from), Some(deadline), _) => visible_from <= deadline,
+            _ => true
+        };
+
+        if !valid {
+            return Err(Status::BadRequest.into());
+        }
+        Ok(())
+    }
+
+    pub fn parsed_prerequisites(&self) -> Result<Prerequisite, Error> {
+        Prerequisite::parse(&serde_json::from_str(&self.prerequisites)?)
+            .map_err(|err| Error::Prerequisites(format!("Task {} in course {}: {}", self.taskid, self.course, err)))
+    }
+
+    pub fn to_json(&self) -> Result<Value, Error> {
+        Ok(json!({
+            "courseName": self.course,
+            "tags": serde_json::from_str::<Value>(&self.tags)?,
+            "orderBy": self.order_by,
+            "prerequisites": serde_json::from_str::<Value>(&self.prerequisites)?,
+            "visibleFrom": self.visible_from,
+            "deadline": self.deadline,
+            "lateUntil": self.late_until,
+            "groupId": self.group_id
+        }))
+    }
+}
+
+fn optional_timestamp(value: &Value) -> Result<Option<i64>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        value => Ok(Some(value.as_i64().ok_or(Status::BadRequest)?))
+    }
+}
+
+/// Task with everything that's hidden from students, for admins.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminTask {
+    taskid: i32,
+    task_description: Value,
+    solution: String,
+    lang: String,
+    tests: Value,
+    version: i32,
+    courses: Vec<Value>
+}
+
+impl AdminTask {
+    fn new(task: Task, mappings: &[Mapping]) -> Result<AdminTask, Error> {
+        let courses = mappings.iter()
+            .filter(|mapping| mapping.taskid == task.taskid)
+            .map(Mapping::to_json)
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(AdminTask {
+            taskid: task.taskid,
+            task_description: serde_json::from_str(&task.task_description)?,
+            solution: task.solution,
+            lang: task.lang,
+            tests: serde_json::from_str(&task.tests)?,
+            version: task.version,
+            courses
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -103,46 +416,64 @@ pub struct PublicTask {
     lang: String,
     tags: Value,
     order_by: i32,
-    prerequisites: Value
+    prerequisites: Value,
+    /// The user hasn't solved the prerequisites yet
+    locked: bool,
+    visible_from: Option<i64>,
+    deadline: Option<i64>,
+    late_until: Option<i64>,
+    group_id: Option<i32>
 }
 
-fn get_all_tasks() -> Vec<Task> {
-    tasks::table.load::<Task>(&crate::database_connection())
-        .expect("Database error")
+fn get_all_tasks(conn: &MysqlConnection) -> Result<Vec<Task>, Error> {
+    Ok(tasks::table.load::<Task>(conn)?)
 }
 
-fn get_course_tasks(course: &str) -> Vec<PublicTask> {
+/// Tasks of a course with the locked status for the user. Unreleased tasks
+/// (see groups::is_released) are only included for tutors and above.
+fn get_course_tasks(conn: &MysqlConnection, course: &str, user: &str) -> Result<Vec<PublicTask>, Error> {
+    let solved = prerequisites::solved_tasks(conn, user, course)?;
+    let show_unreleased = course_role(conn, user, course)? >= Role::Tutor;
+    let now = crate::tools::epoch();
+    let groups = groups::course_groups(conn, course)?;
+
     let mut mapping = courseTask::table.filter(courseTask::course.eq(course))
-        .load::<Mapping>(&crate::database_connection())
-        .expect("Database error")
+        .load::<Mapping>(conn)?
         .into_iter()
+        .filter(|mapping| show_unreleased || groups::is_released(mapping, &groups, now))
         .fold(HashMap::new(), |mut acc, elem| {
             acc.insert(elem.taskid, elem);
             acc
         });
 
     let taskids = mapping.keys().map(|key| key.to_owned()).collect::<Vec<_>>();
-    get_all_tasks().into_iter()
-        .filter(|task| taskids.contains(&&task.taskid))
+    get_all_tasks(conn)?.into_iter()
+        .filter(|task| taskids.contains(&task.taskid))
         .map(|task| {
             let map = mapping.remove(&task.taskid).unwrap();
 
-            PublicTask {
+            Ok(PublicTask {
                 taskid: task.taskid,
-                task_description: serde_json::from_str(&task.task_description).unwrap(),
+                task_description: serde_json::from_str(&task.task_description)?,
                 lang: task.lang,
-                tags: serde_json::from_str(&map.tags).unwrap(),
+                tags: serde_json::from_str(&map.tags)?,
                 order_by: map.order_by,
-                prerequisites: serde_json::from_str(&map.prerequisites).unwrap()
-            }
+                locked: !map.parsed_prerequisites()?.is_met(&solved),
+                prerequisites: serde_json::from_str(&map.prerequisites)?,
+                visible_from: map.visible_from,
+                deadline: map.deadline,
+                late_until: map.late_until,
+                group_id: map.group_id
+            })
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     #[test]
     fn it_works() {
-        println!("{:#?}", super::get_course_tasks("testbeans"));
+        let conn = crate::database_pool().get().unwrap();
+        println!("{:#?}", super::get_course_tasks(&conn, "testbeans", "testuser").unwrap());
     }
 }
\ No newline at end of file
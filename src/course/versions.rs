@@ -0,0 +1,153 @@
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use serde_json::{Map, Value};
+use crate::auth::guards;
+use crate::schema::taskVersions;
+use crate::DbConn;
+use crate::error::Error;
+use super::tasks::{save_task, Mapping, Task};
+
+#[derive(Debug, Queryable)]
+pub struct TaskVersion {
+    pub id: i32,
+    pub taskid: i32,
+    pub version: i32,
+    pub task_description: String,
+    pub solution: String,
+    pub lang: String,
+    pub tests: String,
+    /// Course mappings in the format of Mapping::to_json
+    pub courses: String,
+    pub author: String,
+    pub timestamp: i64
+}
+
+impl TaskVersion {
+    /// Fields that can differ between versions, with JSON fields parsed
+    fn fields(&self) -> Result<Vec<(&'static str, Value)>, Error> {
+        Ok(vec![
+            ("taskDescription", serde_json::from_str(&self.task_description)?),
+            ("solution", Value::String(self.solution.clone())),
+            ("lang", Value::String(self.lang.clone())),
+            ("tests", serde_json::from_str(&self.tests)?),
+            ("courses", serde_json::from_str(&self.courses)?)
+        ])
+    }
+
+    fn to_json(&self) -> Result<Value, Error> {
+        let mut json = json!({
+            "taskid": self.taskid,
+            "version": self.version,
+            "author": self.author,
+            "timestamp": self.timestamp
+        });
+        for (field, value) in self.fields()? {
+            json[field] = value;
+        }
+
+        Ok(json)
+    }
+}
+
+/// Lists all versions of a task, newest first, without their content.
+#[get("/admin/tasks/<taskid>/versions")]
+pub fn route_get_versions(_admin: guards::Admin, taskid: i32, conn: DbConn) -> Result<Json<Vec<Value>>, Error> {
+    let versions = taskVersions::table.filter(taskVersions::taskid.eq(taskid))
+        .select((taskVersions::version, taskVersions::author, taskVersions::timestamp))
+        .order(taskVersions::version.desc())
+        .load::<(i32, String, i64)>(&*conn)?;
+
+    Ok(Json(versions.into_iter()
+        .map(|(version, author, timestamp)| json!({
+            "version": version,
+            "author": author,
+            "timestamp": timestamp
+        }))
+        .collect()))
+}
+
+#[get("/admin/tasks/<taskid>/versions/<version>")]
+pub fn route_get_version(_admin: guards::Admin, taskid: i32, version: i32, conn: DbConn) -> Result<Json<Value>, Error> {
+    Ok(Json(get_version(&conn, taskid, version)?.to_json()?))
+}
+
+/// Fields that differ between two versions of a task, e.g.
+/// {"from": 1, "to": 3, "changes": {"tests": {"from": [...], "to": [...]}}}.
+#[get("/admin/tasks/<taskid>/versions/<from>/diff/<to>")]
+pub fn route_get_version_diff(_admin: guards::Admin, taskid: i32, from: i32, to: i32, conn: DbConn) -> Result<Json<Value>, Error> {
+    let changes = diff(&get_version(&conn, taskid, from)?, &get_version(&conn, taskid, to)?)?;
+
+    Ok(Json(json!({
+        "from": from,
+        "to": to,
+        "changes": changes
+    })))
+}
+
+/// Restores a version of a task, including its course mappings. The restored
+/// state is stored as new version, so the rollback itself can be undone.
+/// Deleted tasks can be restored as well.
+#[post("/admin/tasks/<taskid>/versions/<version>/rollback")]
+pub fn route_post_rollback(admin: guards::Admin, taskid: i32, version: i32, conn: DbConn) -> Result<Json<Value>, Error> {
+    let old = get_version(&conn, taskid, version)?;
+
+    let task = Task {
+        taskid,
+        task_description: old.task_description,
+        solution: old.solution,
+        lang: old.lang,
+        tests: old.tests,
+        version: 0
+    };
+    let mappings = serde_json::from_str::<Vec<Value>>(&old.courses)?
+        .iter()
+        .map(|mapping| Mapping::from_json(taskid, mapping))
+        .collect::<Result<Vec<_>, _>>()?;
+    super::prerequisites::check_graphs(&conn, &[(taskid, &mappings)])?;
+
+    let version = save_task(&conn, &task, &mappings, &admin.name)?;
+
+    Ok(Json(json!({ "version": version })))
+}
+
+fn get_version(conn: &MysqlConnection, taskid: i32, version: i32) -> Result<TaskVersion, Error> {
+    Ok(taskVersions::table.filter(taskVersions::taskid.eq(taskid))
+        .filter(taskVersions::version.eq(version))
+        .first::<TaskVersion>(conn)?)
+}
+
+fn diff(from: &TaskVersion, to: &TaskVersion) -> Result<Map<String, Value>, Error> {
+    Ok(from.fields()?.into_iter()
+        .zip(to.fields()?)
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((field, old), (_, new))| (field.to_string(), json!({ "from": old, "to": new })))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_fields() {
+        let version = |version, tests: &str, courses: &str| TaskVersion {
+            id: version,
+            taskid: 42,
+            version,
+            task_description: r#"{"shortDescription": "Sum"}"#.to_string(),
+            solution: "print(3)".to_string(),
+            lang: "python3".to_string(),
+            tests: tests.to_string(),
+            courses: courses.to_string(),
+            author: "admin".to_string(),
+            timestamp: 0
+        };
+        let from = version(1, r#"[{"input": "1 2", "output": "3"}]"#, "[]");
+        let to = version(2, r#"[ {"output": "3", "input": "1 2"} ]"#, r#"[{"courseName": "testbeans"}]"#);
+
+        let changes = diff(&from, &to).unwrap();
+        assert_eq!(Value::Object(changes), json!({
+            "courses": { "from": [], "to": [{ "courseName": "testbeans" }] }
+        }));
+    }
+}
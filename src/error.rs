@@ -0,0 +1,167 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::Template;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Crate-wide error type. Every variant is turned into a response with a
+/// suitable status code and a JSON body like
+/// `{"code": "database_error", "message": "..."}`.
+/// The `code` field is stable and meant to be used by clients.
+#[derive(Debug)]
+pub enum Error {
+    /// Plain HTTP error, e.g. Status::BadRequest for a malformed request body
+    Status(Status),
+    /// Failed database query (diesel's NotFound is mapped to a 404)
+    Database(diesel::result::Error),
+    /// No database connection available
+    Pool(diesel::r2d2::PoolError),
+    /// The sandbox could not be reached or sent an invalid response
+    Sandbox(String),
+    /// The local evaluator could not run a submission (see evaluation::local)
+    Evaluation(String),
+    /// The LMS rejected or did not answer an LTI service request
+    Lms(String),
+    /// Invalid JSON in the database or in the settings
+    Json(serde_json::Error),
+    /// Malformed task bundle (see course::bundles)
+    Bundle(String),
+    /// Invalid prerequisites of a task (see course::prerequisites)
+    Prerequisites(String),
+    /// Submission to a task whose prerequisites the user hasn't solved yet
+    TaskLocked,
+    /// Submission after the deadline (and late period) of a task
+    DeadlinePassed,
+    /// Browser-facing error page (e.g. for LTI launches); the string is the name
+    /// of the template to render.
+    Template(Status, &'static str)
+}
+
+impl Error {
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Status(status) => *status,
+            Error::Database(diesel::result::Error::NotFound) => Status::NotFound,
+            Error::Database(_) => Status::InternalServerError,
+            Error::Pool(_) => Status::ServiceUnavailable,
+            Error::Sandbox(_) => Status::BadGateway,
+            Error::Evaluation(_) => Status::InternalServerError,
+            Error::Lms(_) => Status::BadGateway,
+            Error::Json(_) => Status::InternalServerError,
+            Error::Bundle(_) => Status::BadRequest,
+            Error::Prerequisites(_) => Status::BadRequest,
+            Error::TaskLocked => Status::Forbidden,
+            Error::DeadlinePassed => Status::Forbidden,
+            Error::Template(status, _) => *status
+        }
+    }
+
+    pub fn code(&self) -> String {
+        match self {
+            Error::Status(status) => status_code(*status),
+            Error::Database(diesel::result::Error::NotFound) => "not_found".to_string(),
+            Error::Database(_) => "database_error".to_string(),
+            Error::Pool(_) => "database_unavailable".to_string(),
+            Error::Sandbox(_) => "sandbox_error".to_string(),
+            Error::Evaluation(_) => "evaluation_error".to_string(),
+            Error::Lms(_) => "lms_error".to_string(),
+            Error::Json(_) => "invalid_json".to_string(),
+            Error::Bundle(_) => "invalid_bundle".to_string(),
+            Error::Prerequisites(_) => "invalid_prerequisites".to_string(),
+            Error::TaskLocked => "task_locked".to_string(),
+            Error::DeadlinePassed => "deadline_passed".to_string(),
+            Error::Template(status, _) => status_code(*status)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Status(status) => write!(f, "{}", status.reason_lossy()),
+            Error::Database(diesel::result::Error::NotFound) => write!(f, "Not Found"),
+            Error::Database(err) => write!(f, "Database error: {}", err),
+            Error::Pool(err) => write!(f, "Database unavailable: {}", err),
+            Error::Sandbox(err) => write!(f, "Sandbox error: {}", err),
+            Error::Evaluation(err) => write!(f, "Evaluation error: {}", err),
+            Error::Lms(err) => write!(f, "LMS error: {}", err),
+            Error::Json(err) => write!(f, "Invalid JSON: {}", err),
+            Error::Bundle(err) => write!(f, "Invalid task bundle: {}", err),
+            Error::Prerequisites(err) => write!(f, "Invalid prerequisites: {}", err),
+            Error::TaskLocked => write!(f, "The prerequisites of the task are not solved yet"),
+            Error::DeadlinePassed => write!(f, "The deadline of the task has passed"),
+            Error::Template(status, template) => write!(f, "{} ({})", status.reason_lossy(), template)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if status.code >= 500 {
+            error_!("{}", self);
+        }
+
+        if let Error::Template(_, template) = self {
+            let empty_context: HashMap<String, String> = HashMap::new();
+            return (status, Template::render(template, empty_context)).respond_to(req);
+        }
+
+        (status, error_body(&self.code(), &self.to_string())).respond_to(req)
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        Error::Status(status)
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(err: diesel::result::Error) -> Self {
+        Error::Database(err)
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for Error {
+    fn from(err: diesel::r2d2::PoolError) -> Self {
+        Error::Pool(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Sandbox(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+/// JSON body for errors without a route response (failing request guards,
+/// unknown routes, ...), so the client always gets the same format.
+#[catch(default)]
+pub fn default_catcher(status: Status, _req: &Request<'_>) -> Json<serde_json::Value> {
+    error_body(&status_code(status), status.reason_lossy())
+}
+
+fn error_body(code: &str, message: &str) -> Json<serde_json::Value> {
+    Json(json!({
+        "code": code,
+        "message": message
+    }))
+}
+
+/// "Not Found" -> "not_found"
+fn status_code(status: Status) -> String {
+    status.reason_lossy()
+        .to_lowercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
@@ -0,0 +1,306 @@
+use rocket::tokio;
+use rocket::tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use crate::SETTINGS;
+use crate::error::Error;
+use super::{Evaluator, EvaluationResult};
+
+/// How to compile and run submissions of a language, e.g.
+/// `{file = "main.c", compile = ["gcc", "-o", "main", "main.c"], run = ["./main"]}`.
+/// Commands are run in a fresh temporary directory containing only `file`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Language {
+    pub file: String,
+    #[serde(default)]
+    pub compile: Option<Vec<String>>,
+    pub run: Vec<String>
+}
+
+/// Resource limits for every process (compiler and test runs).
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Wall clock time
+    pub timeout: Duration,
+    /// CPU seconds (RLIMIT_CPU)
+    pub cpu_time: u64,
+    /// Bytes of address space (RLIMIT_AS)
+    pub memory: u64,
+    /// Bytes per written file (RLIMIT_FSIZE)
+    pub file_size: u64
+}
+
+/// Test case format of tasks evaluated locally: stdin and expected stdout.
+/// Trailing whitespace is ignored when comparing the output.
+#[derive(Debug, Deserialize)]
+struct TestCase {
+    #[serde(default)]
+    input: String,
+    output: String
+}
+
+/// Output of a finished (or killed) process.
+struct Run {
+    /// None if the process was killed after the timeout
+    success: Option<bool>,
+    stdout: String,
+    stderr: String
+}
+
+/// Output kept per process, so a submission printing in an endless loop can't fill the database
+const MAX_OUTPUT: usize = 16 * 1024;
+
+/// Evaluates submissions in child processes on the backend host. Only meant
+/// for trusted code (e.g. CI) or in addition to OS level isolation, the
+/// rlimits prevent accidents but are no sandbox.
+#[derive(Debug)]
+pub struct LocalEvaluator {
+    languages: HashMap<String, Language>,
+    limits: Limits
+}
+
+impl LocalEvaluator {
+    pub fn new(languages: HashMap<String, Language>, limits: Limits) -> LocalEvaluator {
+        LocalEvaluator { languages, limits }
+    }
+
+    /// Languages from evaluation.local.languages; no languages if the key is missing.
+    pub fn from_settings() -> LocalEvaluator {
+        let mib = |key: &str| SETTINGS.get::<u64>(key)
+            .unwrap_or_else(|_| panic!("{} missing in settings", key)) * 1024 * 1024;
+
+        LocalEvaluator::new(
+            SETTINGS.get::<HashMap<String, Language>>("evaluation.local.languages")
+                .unwrap_or_default(),
+            Limits {
+                timeout: Duration::from_secs(SETTINGS.get::<u64>("evaluation.local.timeout")
+                    .expect("evaluation.local.timeout missing in settings")),
+                cpu_time: SETTINGS.get::<u64>("evaluation.local.cpu_time")
+                    .expect("evaluation.local.cpu_time missing in settings"),
+                memory: mib("evaluation.local.memory"),
+                file_size: mib("evaluation.local.file_size")
+            }
+        )
+    }
+
+    pub fn supports(&self, lang: &str) -> bool {
+        self.languages.contains_key(lang)
+    }
+}
+
+#[rocket::async_trait]
+impl Evaluator for LocalEvaluator {
+    async fn evaluate(&self, _taskid: i32, lang: &str, tests: &Value, submission: &str) -> Result<EvaluationResult, Error> {
+        let language = self.languages.get(lang)
+            .ok_or_else(|| Error::Evaluation(format!("Language {} is not evaluated locally", lang)))?;
+        let tests = serde_json::from_value::<Vec<TestCase>>(tests.clone())?;
+
+        let dir = tempfile::tempdir().map_err(evaluation_error)?;
+        tokio::fs::write(dir.path().join(&language.file), submission).await
+            .map_err(evaluation_error)?;
+
+        if let Some(compile) = &language.compile {
+            let run = run(compile, dir.path(), "", self.limits).await?;
+            if run.success != Some(true) {
+                return Ok(EvaluationResult {
+                    result_type: "COMPILE_ERROR".to_string(),
+                    score: 0.0,
+                    simplified: json!({ "compiler": { "stdout": run.stdout, "stderr": run.stderr } }),
+                    details: Value::Null
+                });
+            }
+        }
+
+        let mut result_type = "SUCCESS";
+        let mut passed = 0;
+        let mut results = Vec::new();
+        for test in &tests {
+            let run = run(&language.run, dir.path(), &test.input, self.limits).await?;
+            let status = match run.success {
+                None => "TIMEOUT",
+                Some(false) => "RUNTIME_ERROR",
+                Some(true) if run.stdout.trim_end() != test.output.trim_end() => "WRONG_ANSWER",
+                Some(true) => "SUCCESS"
+            };
+
+            if status == "SUCCESS" {
+                passed += 1;
+            }
+            else if result_type == "SUCCESS" {
+                // The first failed test determines the result
+                result_type = status;
+            }
+
+            results.push(json!({
+                "status": status,
+                "stdin": test.input,
+                "expected": test.output,
+                "stdout": run.stdout,
+                "stderr": run.stderr
+            }));
+        }
+
+        Ok(EvaluationResult {
+            result_type: result_type.to_string(),
+            score: if tests.is_empty() { 1.0 } else { passed as f32 / tests.len() as f32 },
+            simplified: json!({ "testCases": results }),
+            details: Value::Null
+        })
+    }
+}
+
+/// Runs a command with resource limits in its own process group, which is
+/// killed as a whole after the timeout. Only MAX_OUTPUT bytes of stdout and
+/// stderr are kept, the rest is discarded while reading.
+async fn run(command: &[String], dir: &Path, input: &str, limits: Limits) -> Result<Run, Error> {
+    let (program, args) = command.split_first()
+        .ok_or_else(|| Error::Evaluation("Empty command".to_string()))?;
+
+    let mut command = tokio::process::Command::new(program);
+    command.args(args)
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", std::env::var("PATH").unwrap_or_default())
+        .env("HOME", dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    unsafe {
+        // Only async-signal-safe calls are allowed between fork and exec
+        command.pre_exec(move || {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            set_limit(libc::RLIMIT_CPU, limits.cpu_time)?;
+            set_limit(libc::RLIMIT_AS, limits.memory)?;
+            set_limit(libc::RLIMIT_FSIZE, limits.file_size)?;
+            set_limit(libc::RLIMIT_CORE, 0)
+        });
+    }
+
+    let mut child = command.spawn().map_err(evaluation_error)?;
+    let pid = child.id();
+
+    // Write stdin concurrently, the process may not read all of it before writing its output
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    tokio::spawn(async move {
+        // Fails if the process exits without reading its input
+        stdin.write_all(input.as_bytes()).await.ok();
+    });
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let finished = async {
+        let (status, stdout, stderr) = tokio::join!(child.wait(), read_limited(stdout), read_limited(stderr));
+        Ok::<_, io::Error>((status?, stdout?, stderr?))
+    };
+
+    match tokio::time::timeout(limits.timeout, finished).await {
+        Ok(output) => {
+            let (status, stdout, stderr) = output.map_err(evaluation_error)?;
+            Ok(Run {
+                success: Some(status.success()),
+                stdout: String::from_utf8_lossy(&stdout).to_string(),
+                stderr: String::from_utf8_lossy(&stderr).to_string()
+            })
+        },
+        Err(_) => {
+            if let Some(pid) = pid {
+                unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL); }
+            }
+            Ok(Run { success: None, stdout: String::new(), stderr: String::new() })
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+#[allow(clippy::useless_conversion)]
+fn set_limit(resource: Resource, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value.into(),
+        rlim_max: value.into()
+    };
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads the first MAX_OUTPUT bytes of a pipe and drains the rest, so the
+/// process doesn't block on a full pipe.
+async fn read_limited(mut pipe: impl AsyncRead + Unpin) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    (&mut pipe).take(MAX_OUTPUT as u64).read_to_end(&mut output).await?;
+    tokio::io::copy(&mut pipe, &mut tokio::io::sink()).await?;
+    Ok(output)
+}
+
+fn evaluation_error(err: io::Error) -> Error {
+    Error::Evaluation(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn shell_tests() {
+        let languages = vec![("sh".to_string(), Language {
+            file: "main.sh".to_string(),
+            compile: None,
+            run: vec!["sh".to_string(), "main.sh".to_string()]
+        })].into_iter().collect();
+        let evaluator = LocalEvaluator::new(languages, Limits {
+            timeout: Duration::from_secs(1),
+            cpu_time: 5,
+            memory: 256 * 1024 * 1024,
+            file_size: 1024 * 1024
+        });
+        let tests = json!([
+            { "input": "1 2\n", "output": "3\n" },
+            { "input": "20 22", "output": "42" }
+        ]);
+
+        let result = evaluator.evaluate(1, "sh", &tests, "read a b; echo $((a + b))").await.unwrap();
+        assert_eq!(result.result_type, "SUCCESS");
+        assert_eq!(result.score, 1.0);
+
+        let result = evaluator.evaluate(1, "sh", &tests, "read a b; echo 3").await.unwrap();
+        assert_eq!(result.result_type, "WRONG_ANSWER");
+        assert_eq!(result.score, 0.5);
+
+        let result = evaluator.evaluate(1, "sh", &tests, "exit 1").await.unwrap();
+        assert_eq!(result.result_type, "RUNTIME_ERROR");
+
+        let result = evaluator.evaluate(1, "sh", &tests, "sleep 10").await.unwrap();
+        assert_eq!(result.result_type, "TIMEOUT");
+        assert_eq!(result.score, 0.0);
+
+        assert!(evaluator.evaluate(1, "python3", &tests, "print(3)").await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn limited_output() {
+        let command = vec!["sh".to_string(), "-c".to_string(), "yes | head -c 1000000".to_string()];
+        let limits = Limits {
+            timeout: Duration::from_secs(5),
+            cpu_time: 5,
+            memory: 256 * 1024 * 1024,
+            file_size: 1024 * 1024
+        };
+
+        let run = run(&command, &std::env::temp_dir(), "", limits).await.unwrap();
+        assert_eq!(run.success, Some(true));
+        assert_eq!(run.stdout.len(), MAX_OUTPUT);
+    }
+}
@@ -0,0 +1,240 @@
+use diesel::prelude::*;
+use rocket::tokio;
+use rocket::tokio::sync::{mpsc, Semaphore};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::schema::{submissionHistory, submissions, tasks};
+use crate::{SETTINGS, DbConn, DbPool};
+use crate::error::Error;
+use crate::events::{CourseEvent, Hub};
+use sandbox::{Sandboxes, SandboxConfig};
+use local::LocalEvaluator;
+
+pub mod sandbox;
+pub mod local;
+pub mod reevaluation;
+
+/// Result type of submissions that are not evaluated yet.
+pub const PENDING: &str = "PENDING";
+/// Result type of submissions a worker has claimed and is evaluating.
+pub const EVALUATING: &str = "EVALUATING";
+/// Result type of submissions no sandbox could evaluate, even after retries.
+pub const EVALUATION_UNAVAILABLE: &str = "EVALUATION_UNAVAILABLE";
+/// Result type of submissions that cannot be evaluated at all, e.g. because
+/// their task was deleted, has invalid tests or the local evaluator failed.
+pub const EVALUATION_ERROR: &str = "EVALUATION_ERROR";
+/// Result types that say nothing about the submission itself, e.g. during a
+/// sandbox outage. They don't count as attempts.
+pub const NO_RESULT: [&str; 4] = [PENDING, EVALUATING, EVALUATION_ERROR, EVALUATION_UNAVAILABLE];
+
+/// Result of an evaluation, as stored in the submissions table.
+#[derive(Debug, Clone)]
+pub struct EvaluationResult {
+    pub result_type: String,
+    /// 0.0 - 1.0
+    pub score: f32,
+    pub simplified: Value,
+    pub details: Value
+}
+
+/// Something that runs the tests of a task against a submission.
+#[rocket::async_trait]
+pub trait Evaluator: Send + Sync {
+    async fn evaluate(&self, taskid: i32, lang: &str, tests: &Value, submission: &str) -> Result<EvaluationResult, Error>;
+}
+
+/// Evaluates the languages configured in evaluation.local.languages with the
+/// local evaluator and everything else with the sandbox service.
+pub struct Evaluators {
+    sandboxes: Arc<Sandboxes>,
+    local: LocalEvaluator
+}
+
+#[rocket::async_trait]
+impl Evaluator for Evaluators {
+    async fn evaluate(&self, taskid: i32, lang: &str, tests: &Value, submission: &str) -> Result<EvaluationResult, Error> {
+        if self.local.supports(lang) {
+            self.local.evaluate(taskid, lang, tests, submission).await
+        }
+        else {
+            self.sandboxes.evaluate(taskid, lang, tests, submission).await
+        }
+    }
+}
+
+/// Queue of submissions waiting for evaluation. Submissions are stored with
+/// result type PENDING before they are queued, so the queue itself only holds
+/// submission ids and is rebuilt from the database on startup.
+/// At most evaluation.workers submissions are evaluated at the same time.
+/// Progress is published as events to the submitting user.
+pub struct Queue {
+    sender: mpsc::UnboundedSender<i32>,
+    evaluators: Arc<Evaluators>
+}
+
+impl Queue {
+    /// Starts the workers and queues all submissions that are still pending
+    /// or evaluating (e.g. because the backend was stopped during their evaluation).
+    pub fn start(pool: DbPool, hub: Hub) -> Result<Queue, Error> {
+        let workers = SETTINGS.get::<usize>("evaluation.workers")
+            .expect("evaluation.workers missing in settings");
+        let health_check_interval = SETTINGS.get::<u64>("sandbox.health_check_interval")
+            .expect("sandbox.health_check_interval missing in settings");
+
+        let sandboxes = Arc::new(Sandboxes::new(SandboxConfig::from_settings()));
+        sandboxes.start_health_checks(Duration::from_secs(health_check_interval));
+        let evaluators = Arc::new(Evaluators {
+            sandboxes,
+            local: LocalEvaluator::from_settings()
+        });
+
+        let (sender, mut receiver) = mpsc::unbounded_channel::<i32>();
+
+        let conn = pool.get()?;
+        diesel::update(submissions::table.filter(submissions::resultType.eq(EVALUATING)))
+            .set(submissions::resultType.eq(PENDING))
+            .execute(&*conn)?;
+        let pending = submissions::table.filter(submissions::resultType.eq(PENDING))
+            .select(submissions::id)
+            .order(submissions::id)
+            .load::<i32>(&*conn)?;
+        drop(conn);
+        if !pending.is_empty() {
+            info_!("Queueing {} pending submissions", pending.len());
+        }
+        for id in pending {
+            sender.send(id).ok();
+        }
+
+        let semaphore = Arc::new(Semaphore::new(workers));
+        let worker_evaluators = evaluators.clone();
+        tokio::spawn(async move {
+            while let Some(id) = receiver.recv().await {
+                let permit = semaphore.clone().acquire_owned().await
+                    .expect("Semaphore closed");
+                let (pool, hub, evaluators) = (pool.clone(), hub.clone(), worker_evaluators.clone());
+
+                tokio::spawn(async move {
+                    if let Err(err) = evaluate(&pool, &hub, evaluators.as_ref(), id).await {
+                        error_!("Evaluation of submission {} failed: {}", id, err);
+                    }
+                    drop(permit);
+                });
+            }
+        });
+
+        Ok(Queue { sender, evaluators })
+    }
+
+    /// Queues a submission that has been stored with result type PENDING.
+    pub fn push(&self, id: i32) {
+        // The receiver lives as long as the runtime
+        self.sender.send(id).ok();
+    }
+
+    pub fn evaluator(&self) -> &dyn Evaluator {
+        self.evaluators.as_ref()
+    }
+}
+
+/// Evaluates a pending submission against the current version of its task and
+/// stores the result with that version. The submission is claimed by setting
+/// its result type to EVALUATING first; submissions that are not pending
+/// (anymore), e.g. queued twice, are skipped. Submissions whose task is missing or has invalid
+/// tests are stored as EVALUATION_ERROR, so they don't stay pending forever.
+async fn evaluate(pool: &DbPool, hub: &Hub, evaluator: &dyn Evaluator, id: i32) -> Result<(), Error> {
+    let (user, course, taskid, content, task, best_score, reevaluated) = {
+        let conn = DbConn::get(pool).await?;
+        let claimed = diesel::update(submissions::table.filter(submissions::id.eq(id)))
+            .filter(submissions::resultType.eq(PENDING))
+            .set(submissions::resultType.eq(EVALUATING))
+            .execute(&*conn)?;
+        if claimed == 0 {
+            return Ok(());
+        }
+
+        let (user, course, taskid, content) = submissions::table.filter(submissions::id.eq(id))
+            .select((submissions::user, submissions::course, submissions::taskid, submissions::content))
+            .first::<(String, String, i32, String)>(&*conn)?;
+
+        let task = tasks::table.filter(tasks::taskid.eq(taskid))
+            .select((tasks::lang, tasks::tests, tasks::version))
+            .first::<(String, String, i32)>(&*conn)
+            .optional()?;
+
+        let best_score = best_score(&conn, &user, &course, taskid)?;
+        let reevaluated = submissionHistory::table.filter(submissionHistory::submission.eq(id))
+            .select(submissionHistory::id)
+            .first::<i32>(&*conn)
+            .optional()?
+            .is_some();
+        (user, course, taskid, content, task, best_score, reevaluated)
+    };
+
+    hub.send_to_user(&user, &course, CourseEvent::SubmissionEvaluating { id, taskid });
+
+    let version = task.as_ref().map(|(_, _, version)| *version);
+    let task = match task {
+        Some((lang, tests, _)) => serde_json::from_str::<Value>(&tests)
+            .map(|tests| (lang, tests))
+            .map_err(|err| format!("Invalid tests of task {}: {}", taskid, err)),
+        None => Err(format!("Task {} does not exist", taskid))
+    };
+
+    let evaluated = match task {
+        Ok((lang, tests)) => evaluator.evaluate(taskid, &lang, &tests, &content).await,
+        Err(err) => Err(Error::Evaluation(err))
+    };
+    let EvaluationResult { result_type, score, simplified, details } = match evaluated {
+        Ok(result) => result,
+        Err(Error::Sandbox(err)) => {
+            error_!("No sandbox could evaluate submission {}: {}", id, err);
+            EvaluationResult {
+                result_type: EVALUATION_UNAVAILABLE.to_string(),
+                score: 0.0,
+                simplified: json!({ "error": "Evaluation unavailable, please try again later" }),
+                details: Value::Null
+            }
+        },
+        Err(err) => {
+            error_!("Submission {} cannot be evaluated: {}", id, err);
+            EvaluationResult {
+                result_type: EVALUATION_ERROR.to_string(),
+                score: 0.0,
+                simplified: json!({ "error": err.to_string() }),
+                details: Value::Null
+            }
+        }
+    };
+
+    diesel::update(submissions::table.filter(submissions::id.eq(id)))
+        .set((
+            submissions::resultType.eq(&result_type),
+            submissions::simplified.eq(serde_json::to_string(&simplified)?),
+            submissions::details.eq(serde_json::to_string(&details)?),
+            submissions::score.eq(score),
+            submissions::taskVersion.eq(version)
+        ))
+        .execute(&*DbConn::get(pool).await?)?;
+
+    // Re-evaluations may also lower the grade
+    if reevaluated || result_type == "SUCCESS" || best_score.is_none_or(|best| score > best) {
+        crate::auth::lti_outcomes::report_grade_in_background(pool, &user, &course);
+    }
+
+    hub.send_to_user(&user, &course, CourseEvent::SubmissionEvaluated { id, taskid, result_type, score });
+
+    Ok(())
+}
+
+/// Best score of all evaluated submissions of a user for a task.
+fn best_score(conn: &MysqlConnection, user: &str, course: &str, taskid: i32) -> Result<Option<f32>, Error> {
+    use diesel::expression::dsl::max;
+    Ok(submissions::table.filter(submissions::user.eq(user))
+        .filter(submissions::course.eq(course))
+        .filter(submissions::taskid.eq(taskid))
+        .filter(submissions::resultType.ne_all(vec![PENDING, EVALUATING]))
+        .select(max(submissions::score))
+        .first::<Option<f32>>(conn)?)
+}
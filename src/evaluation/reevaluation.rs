@@ -0,0 +1,171 @@
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use crate::auth::guards;
+use crate::schema::{reevaluations, submissionHistory, submissions};
+use crate::DbConn;
+use crate::error::Error;
+use super::{Queue, EVALUATING, PENDING};
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct Reevaluation {
+    pub id: i32,
+    pub course: String,
+    /// None if all tasks of the course were re-evaluated
+    pub taskid: Option<i32>,
+    pub admin: String,
+    pub timestamp: i64
+}
+
+/// Solved status of a student for a task before and after a re-evaluation.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StatusChange {
+    pub user: String,
+    pub taskid: i32,
+    pub before: bool,
+    pub after: bool
+}
+
+/// Re-evaluates all submissions of a course, or of one task if the body
+/// contains a taskid: {"taskid": 42}. The current results are kept in the
+/// submissionHistory table, the submissions are queued like new ones.
+#[post("/admin/courses/<course>/reevaluations", data = "<data>")]
+pub fn route_post_reevaluation(admin: guards::Admin, course: String, data: Json<Value>, conn: DbConn, queue: &State<Queue>) -> Result<Json<Value>, Error> {
+    let taskid = match data.get("taskid") {
+        None | Some(Value::Null) => None,
+        Some(taskid) => Some(taskid.as_i64().ok_or(Status::BadRequest)? as i32)
+    };
+
+    if crate::course::name_to_title(&conn, &course)?.is_none() {
+        return Err(Status::NotFound.into());
+    }
+
+    let (reevaluation, ids) = conn.transaction::<_, Error, _>(|| {
+        diesel::insert_into(reevaluations::table)
+            .values((
+                reevaluations::course.eq(&course),
+                reevaluations::taskid.eq(taskid),
+                reevaluations::admin.eq(&admin.name),
+                reevaluations::timestamp.eq(crate::tools::epoch())
+            ))
+            .execute(&*conn)?;
+        let reevaluation = crate::tools::last_insert_id(&conn)?;
+
+        // Pending submissions are evaluated with the current tests anyway
+        let mut query = submissions::table.filter(submissions::course.eq(&course))
+            .filter(submissions::resultType.ne_all(vec![PENDING, EVALUATING]))
+            .select((submissions::id, submissions::resultType, submissions::simplified, submissions::details, submissions::score, submissions::taskVersion))
+            .into_boxed();
+        if let Some(taskid) = taskid {
+            query = query.filter(submissions::taskid.eq(taskid));
+        }
+        let results = query.load::<(i32, String, String, String, f32, Option<i32>)>(&*conn)?;
+
+        let history = results.iter()
+            .map(|(id, result_type, simplified, details, score, task_version)| (
+                submissionHistory::submission.eq(*id),
+                submissionHistory::reevaluation.eq(reevaluation),
+                submissionHistory::resultType.eq(result_type),
+                submissionHistory::simplified.eq(simplified),
+                submissionHistory::details.eq(details),
+                submissionHistory::score.eq(*score),
+                submissionHistory::taskVersion.eq(*task_version)
+            ))
+            .collect::<Vec<_>>();
+        diesel::insert_into(submissionHistory::table)
+            .values(&history)
+            .execute(&*conn)?;
+
+        let ids = results.into_iter().map(|(id, ..)| id).collect::<Vec<_>>();
+        diesel::update(submissions::table.filter(submissions::id.eq_any(&ids)))
+            .set(submissions::resultType.eq(PENDING))
+            .execute(&*conn)?;
+
+        Ok((reevaluation, ids))
+    })?;
+
+    for id in &ids {
+        queue.push(*id);
+    }
+
+    Ok(Json(json!({
+        "id": reevaluation,
+        "submissions": ids.len()
+    })))
+}
+
+#[get("/admin/courses/<course>/reevaluations")]
+pub fn route_get_reevaluations(_admin: guards::Admin, course: String, conn: DbConn) -> Result<Json<Vec<Reevaluation>>, Error> {
+    Ok(Json(reevaluations::table.filter(reevaluations::course.eq(&course))
+        .order(reevaluations::id.desc())
+        .load::<Reevaluation>(&*conn)?))
+}
+
+/// Report of a re-evaluation: number of submissions still pending and all
+/// students whose solved status of a task changed. The report is final as
+/// soon as nothing is pending anymore.
+#[get("/admin/reevaluations/<id>")]
+pub fn route_get_reevaluation(_admin: guards::Admin, id: i32, conn: DbConn) -> Result<Json<Value>, Error> {
+    let reevaluation = reevaluations::table.filter(reevaluations::id.eq(id))
+        .first::<Reevaluation>(&*conn)?;
+
+    let results = submissionHistory::table
+        .inner_join(submissions::table.on(submissions::id.eq(submissionHistory::submission)))
+        .filter(submissionHistory::reevaluation.eq(id))
+        .select((submissions::user, submissions::taskid, submissionHistory::resultType, submissions::resultType))
+        .load::<(String, i32, String, String)>(&*conn)?;
+
+    let pending = results.iter()
+        .filter(|(_, _, _, after)| after == PENDING || after == EVALUATING)
+        .count();
+
+    Ok(Json(json!({
+        "reevaluation": reevaluation,
+        "submissions": results.len(),
+        "pending": pending,
+        "changes": status_changes(&results)
+    })))
+}
+
+/// Takes (user, taskid, resultType before, resultType after) of all
+/// re-evaluated submissions. A task counts as solved if any submission succeeded.
+fn status_changes(results: &[(String, i32, String, String)]) -> Vec<StatusChange> {
+    let mut solved = BTreeMap::new();
+    for (user, taskid, before, after) in results {
+        let entry = solved.entry((user, *taskid)).or_insert((false, false));
+        entry.0 |= before == "SUCCESS";
+        entry.1 |= after == "SUCCESS";
+    }
+
+    solved.into_iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(|((user, taskid), (before, after))| StatusChange { user: user.clone(), taskid, before, after })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_status() {
+        let result = |user: &str, taskid, before: &str, after: &str| {
+            (user.to_string(), taskid, before.to_string(), after.to_string())
+        };
+        let results = vec![
+            result("alice", 1, "SUCCESS", "WRONG_ANSWER"),
+            result("alice", 1, "WRONG_ANSWER", "SUCCESS"),
+            result("alice", 2, "SUCCESS", "WRONG_ANSWER"),
+            result("bob", 1, "WRONG_ANSWER", "SUCCESS"),
+            result("bob", 2, "WRONG_ANSWER", "PENDING")
+        ];
+
+        assert_eq!(status_changes(&results), vec![
+            StatusChange { user: "alice".to_string(), taskid: 2, before: true, after: false },
+            StatusChange { user: "bob".to_string(), taskid: 1, before: false, after: true }
+        ]);
+    }
+}
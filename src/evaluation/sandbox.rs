@@ -0,0 +1,254 @@
+use rand::seq::SliceRandom;
+use reqwest::header::CONTENT_TYPE;
+use rocket::tokio;
+use serde_json::Value;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use crate::SETTINGS;
+use crate::error::Error;
+use super::{Evaluator, EvaluationResult};
+
+#[derive(Debug, Clone)]
+pub struct SandboxConfig {
+    pub urls: Vec<String>,
+    /// Timeout of a single evaluation request
+    pub timeout: Duration,
+    /// Number of retries on other sandboxes after a failed request
+    pub retries: u32,
+    /// Wait time before the first retry, doubled for every further retry
+    pub backoff: Duration,
+    /// Path of the health endpoint, relative to the sandbox URL
+    pub health_path: String
+}
+
+impl SandboxConfig {
+    pub fn from_settings() -> SandboxConfig {
+        let secs = |key: &str| Duration::from_secs(SETTINGS.get::<u64>(key)
+            .unwrap_or_else(|_| panic!("{} missing in settings", key)));
+
+        SandboxConfig {
+            urls: SETTINGS.get::<Vec<String>>("sandbox.urls")
+                .expect("sandbox.urls missing in settings"),
+            timeout: secs("sandbox.timeout"),
+            retries: SETTINGS.get::<u32>("sandbox.retries")
+                .expect("sandbox.retries missing in settings"),
+            backoff: secs("sandbox.retry_backoff"),
+            health_path: SETTINGS.get::<String>("sandbox.health_path")
+                .expect("sandbox.health_path missing in settings")
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sandbox {
+    url: String,
+    healthy: AtomicBool
+}
+
+/// Evaluator for the external sandbox service. Sandboxes that fail a request or
+/// a health probe are taken out of rotation until they pass a health probe again.
+#[derive(Debug)]
+pub struct Sandboxes {
+    config: SandboxConfig,
+    sandboxes: Vec<Sandbox>,
+    client: reqwest::Client
+}
+
+impl Sandboxes {
+    pub fn new(config: SandboxConfig) -> Sandboxes {
+        let sandboxes = config.urls.iter()
+            .map(|url| Sandbox {
+                url: url.trim_end_matches('/').to_string(),
+                healthy: AtomicBool::new(true)
+            })
+            .collect();
+
+        Sandboxes {
+            client: reqwest::Client::builder()
+                .timeout(config.timeout)
+                .build()
+                .expect("Failed to build HTTP client"),
+            config,
+            sandboxes
+        }
+    }
+
+    /// Probes all sandboxes every `interval`.
+    pub fn start_health_checks(self: &Arc<Self>, interval: Duration) {
+        let sandboxes = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                sandboxes.check_health().await;
+            }
+        });
+    }
+
+    /// Probes all sandboxes once and updates their health.
+    pub async fn check_health(&self) {
+        for sandbox in &self.sandboxes {
+            let healthy = self.client.get(format!("{}{}", sandbox.url, self.config.health_path))
+                .send()
+                .await
+                .is_ok_and(|response| response.status().is_success());
+
+            if sandbox.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                if healthy {
+                    info_!("Sandbox {} is healthy again", sandbox.url);
+                }
+                else {
+                    warn_!("Sandbox {} failed health probe", sandbox.url);
+                }
+            }
+        }
+    }
+
+    /// URLs of the sandboxes that are currently in rotation.
+    pub fn healthy(&self) -> Vec<&str> {
+        self.sandboxes.iter()
+            .filter(|sandbox| sandbox.healthy.load(Ordering::Relaxed))
+            .map(|sandbox| sandbox.url.as_str())
+            .collect()
+    }
+
+    async fn request(&self, url: &str, body: &Value) -> Result<EvaluationResult, Error> {
+        let result = self.client.post(format!("{}/evaluate", url))
+            .header(CONTENT_TYPE, "application/json")
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        Ok(EvaluationResult {
+            result_type: result["type"].as_str()
+                .ok_or_else(|| Error::Sandbox("Missing result type".to_string()))?
+                .to_string(),
+            score: result["score"].as_f64()
+                .ok_or_else(|| Error::Sandbox("Missing score".to_string()))? as f32,
+            simplified: result["simplified"].clone(),
+            details: result["details"].clone()
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Evaluator for Sandboxes {
+    /// Failed requests are retried on another sandbox (if there is one) with
+    /// exponential backoff. If no sandbox is healthy, all of them are tried,
+    /// as their last probe may be outdated.
+    async fn evaluate(&self, taskid: i32, lang: &str, tests: &Value, submission: &str) -> Result<EvaluationResult, Error> {
+        let mut candidates = self.sandboxes.iter()
+            .filter(|sandbox| sandbox.healthy.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            candidates = self.sandboxes.iter().collect();
+        }
+        if candidates.is_empty() {
+            return Err(Error::Sandbox("No sandbox configured".to_string()));
+        }
+        candidates.shuffle(&mut rand::thread_rng());
+
+        let body = json!({
+            "taskid": taskid,
+            "submission": submission,
+            "lang": lang,
+            "tests": tests
+        });
+
+        let mut backoff = self.config.backoff;
+        let mut last_error = None;
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            let sandbox = candidates[attempt as usize % candidates.len()];
+            match self.request(&sandbox.url, &body).await {
+                Ok(result) => return Ok(result),
+                Err(err) => {
+                    warn_!("Sandbox {} failed (attempt {}): {}", sandbox.url, attempt + 1, err);
+                    sandbox.healthy.store(false, Ordering::Relaxed);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.expect("At least one attempt is made"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::mock_http_server;
+
+    fn config(urls: Vec<String>) -> SandboxConfig {
+        SandboxConfig {
+            urls,
+            timeout: Duration::from_secs(2),
+            retries: 2,
+            backoff: Duration::from_millis(10),
+            health_path: "/health".to_string()
+        }
+    }
+
+    /// URL of a server that closes every connection without response. The
+    /// listener stays bound, so other tests can't get the port.
+    async fn dead_url() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        url
+    }
+
+    #[rocket::async_test]
+    async fn failover() {
+        let (url, requests) = mock_http_server(|request| match request.path.as_str() {
+            "/evaluate" => (200, json!({ "type": "SUCCESS", "score": 1.0, "simplified": {} }).to_string()),
+            _ => (200, String::new())
+        }).await;
+        let dead = dead_url().await;
+        let sandboxes = Sandboxes::new(config(vec![dead.clone(), url.clone()]));
+
+        // The order is random, so evaluate until the dead sandbox has been tried
+        let mut evaluations = 0;
+        while sandboxes.healthy().len() > 1 && evaluations < 50 {
+            let result = sandboxes.evaluate(1, "python3", &json!([]), "print(42)").await.unwrap();
+            assert_eq!(result.result_type, "SUCCESS");
+            assert_eq!(result.score, 1.0);
+            evaluations += 1;
+        }
+        assert_eq!(sandboxes.healthy(), vec![url.as_str()]);
+        assert_eq!(requests.lock().unwrap().len(), evaluations);
+
+        sandboxes.check_health().await;
+        assert_eq!(sandboxes.healthy(), vec![url.as_str()]);
+    }
+
+    #[rocket::async_test]
+    async fn all_sandboxes_failing() {
+        let (url, requests) = mock_http_server(|request| match request.path.as_str() {
+            "/evaluate" => (500, "crashed".to_string()),
+            _ => (200, String::new())
+        }).await;
+        let sandboxes = Sandboxes::new(config(vec![url]));
+
+        assert!(sandboxes.evaluate(1, "python3", &json!([]), "print(42)").await.is_err());
+        // First attempt and two retries
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert!(sandboxes.healthy().is_empty());
+
+        // Back in rotation after a successful probe
+        sandboxes.check_health().await;
+        assert_eq!(sandboxes.healthy().len(), 1);
+    }
+}
@@ -0,0 +1,173 @@
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Shutdown, State};
+use serde_json::Value;
+use crate::auth::guards;
+use crate::schema::announcements;
+use crate::DbConn;
+use crate::error::Error;
+
+/// Events pushed to clients via the event stream of a course.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum CourseEvent {
+    SubmissionQueued { id: i32, taskid: i32 },
+    SubmissionEvaluating { id: i32, taskid: i32 },
+    #[serde(rename_all = "camelCase")]
+    SubmissionEvaluated { id: i32, taskid: i32, result_type: String, score: f32 },
+    Announcement(Announcement)
+}
+
+impl CourseEvent {
+    /// Name of the SSE event, i.e. the type tag
+    fn name(&self) -> &'static str {
+        match self {
+            CourseEvent::SubmissionQueued { .. } => "submissionQueued",
+            CourseEvent::SubmissionEvaluating { .. } => "submissionEvaluating",
+            CourseEvent::SubmissionEvaluated { .. } => "submissionEvaluated",
+            CourseEvent::Announcement(_) => "announcement"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct Announcement {
+    pub id: i32,
+    pub course: String,
+    pub author: String,
+    pub message: String,
+    pub timestamp: i64
+}
+
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub course: String,
+    /// Only this user gets the event; None for events to the whole course
+    pub user: Option<String>,
+    pub event: CourseEvent
+}
+
+/// Distributes events to all open event streams. Events are not stored, so
+/// clients that connect later (or lag too far behind) miss them.
+#[derive(Debug, Clone)]
+pub struct Hub {
+    sender: broadcast::Sender<Envelope>
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Hub { sender: broadcast::channel(1024).0 }
+    }
+}
+
+impl Hub {
+    pub fn send_to_user(&self, user: &str, course: &str, event: CourseEvent) {
+        self.send(Envelope { course: course.to_string(), user: Some(user.to_string()), event });
+    }
+
+    pub fn send_to_course(&self, course: &str, event: CourseEvent) {
+        self.send(Envelope { course: course.to_string(), user: None, event });
+    }
+
+    fn send(&self, envelope: Envelope) {
+        // Fails only if no stream is open
+        self.sender.send(envelope).ok();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.sender.subscribe()
+    }
+}
+
+/// Event stream with the submission events of the user and the announcements
+/// of the course. Clients get a `lagged` event if they missed events and
+/// should reload their data then.
+#[get("/courses/<course>/events")]
+pub fn route_get_events(user: guards::User, course: String, hub: &State<Hub>, mut shutdown: Shutdown) -> Result<EventStream![], Error> {
+    if course != user.course {
+        return Err(Status::Forbidden.into());
+    }
+
+    let mut events = hub.subscribe();
+    Ok(EventStream! {
+        loop {
+            let envelope = select! {
+                envelope = events.recv() => match envelope {
+                    Ok(envelope) => envelope,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => {
+                        yield Event::data("{}").event("lagged");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break
+            };
+
+            if envelope.course == user.course && envelope.user.as_ref().is_none_or(|name| name == &user.name) {
+                yield Event::json(&envelope.event).event(envelope.event.name());
+            }
+        }
+    })
+}
+
+#[get("/courses/<course>/announcements")]
+pub fn route_get_announcements(user: guards::User, course: String, conn: DbConn) -> Result<Json<Vec<Announcement>>, Error> {
+    if course != user.course {
+        return Err(Status::Forbidden.into());
+    }
+
+    Ok(Json(announcements::table.filter(announcements::course.eq(&course))
+        .order(announcements::id.desc())
+        .load::<Announcement>(&*conn)?))
+}
+
+/// Posts an announcement to the course, body: {"message": "..."}.
+#[post("/courses/<course>/announcements", data = "<data>")]
+pub fn route_post_announcement(instructor: guards::Instructor, course: String, data: Json<Value>, conn: DbConn, hub: &State<Hub>) -> Result<Json<Announcement>, Error> {
+    if course != instructor.course {
+        return Err(Status::Forbidden.into());
+    }
+
+    let message = data["message"].as_str()
+        .ok_or(Status::BadRequest)?;
+
+    let announcement = conn.transaction::<_, Error, _>(|| {
+        diesel::insert_into(announcements::table)
+            .values((
+                announcements::course.eq(&course),
+                announcements::author.eq(&instructor.name),
+                announcements::message.eq(message),
+                announcements::timestamp.eq(crate::tools::epoch())
+            ))
+            .execute(&*conn)?;
+
+        Ok(announcements::table.filter(announcements::id.eq(crate::tools::last_insert_id(&conn)?))
+            .first::<Announcement>(&*conn)?)
+    })?;
+
+    hub.send_to_course(&course, CourseEvent::Announcement(announcement.clone()));
+
+    Ok(Json(announcement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_format() {
+        let event = CourseEvent::SubmissionEvaluated { id: 1, taskid: 42, result_type: "SUCCESS".to_string(), score: 1.0 };
+        assert_eq!(event.name(), "submissionEvaluated");
+        assert_eq!(serde_json::to_value(&event).unwrap(), json!({
+            "type": "submissionEvaluated",
+            "id": 1,
+            "taskid": 42,
+            "resultType": "SUCCESS",
+            "score": 1.0
+        }));
+    }
+}
@@ -1,3 +1,6 @@
+// diesel's and rocket's derive macros emit impls inside of const blocks
+#![allow(non_local_definitions)]
+
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate diesel;
 #[macro_use] extern crate lazy_static;
@@ -6,8 +9,14 @@
 
 use config::Config;
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use rocket::serde::json::Json;
+use rocket::request::{Request, FromRequest, Outcome};
+use rocket::http::Status;
+use rocket::State;
 use serde_json::Value;
+use std::ops::Deref;
+use std::time::Duration;
 
 #[allow(non_snake_case)]
 pub mod schema;
@@ -15,7 +24,10 @@ pub mod schema;
 pub mod auth;
 pub mod user;
 pub mod course;
+pub mod evaluation;
+pub mod events;
 pub mod tools;
+pub mod error;
 
 lazy_static! {
     pub static ref SETTINGS: Config = {
@@ -37,7 +49,11 @@ pub fn route_get_info() -> Json<Value> {
     }))
 }
 
-pub fn database_connection() -> MysqlConnection {
+pub type DbPool = Pool<ConnectionManager<MysqlConnection>>;
+
+/// Creates the database connection pool. Rocket manages the pool, routes get
+/// their connections via the `DbConn` request guard.
+pub fn database_pool() -> DbPool {
     let host: String = SETTINGS.get("database.host")
         .expect("Missing database host in settings file");
     let port: u32 = SETTINGS.get("database.port")
@@ -48,11 +64,69 @@ pub fn database_connection() -> MysqlConnection {
         .expect("Missing database password in settings file");
     let database: String = SETTINGS.get("database.database")
         .expect("Missing database name in settings file");
+    let pool_size: u32 = SETTINGS.get("database.pool_size")
+        .expect("Missing database pool size in settings file");
+    let connection_timeout: u64 = SETTINGS.get("database.connection_timeout")
+        .expect("Missing database connection timeout in settings file");
+    let idle_timeout: u64 = SETTINGS.get("database.idle_timeout")
+        .expect("Missing database idle timeout in settings file");
+    // If not set, r2d2 keeps pool_size connections open
+    let min_idle = SETTINGS.get::<u32>("database.min_idle").ok();
 
-    MysqlConnection::establish(&format!("mysql://{}:{}@{}:{}/{}", user, password, host, port, database))
+    let manager = ConnectionManager::<MysqlConnection>::new(
+        format!("mysql://{}:{}@{}:{}/{}", user, password, host, port, database)
+    );
+
+    Pool::builder()
+        .max_size(pool_size)
+        .min_idle(min_idle)
+        .connection_timeout(Duration::from_secs(connection_timeout))
+        .idle_timeout(Some(Duration::from_secs(idle_timeout)))
+        .build(manager)
         .expect("Failed to open database connection")
 }
 
+/// A connection from the pool, returned to it when the request is done.
+pub struct DbConn(PooledConnection<ConnectionManager<MysqlConnection>>);
+
+impl DbConn {
+    /// Takes a connection from the pool on a blocking thread, so an exhausted
+    /// pool doesn't block the async runtime. Fails after
+    /// database.connection_timeout seconds if no connection becomes available.
+    pub async fn get(pool: &DbPool) -> Result<DbConn, error::Error> {
+        let pool = pool.clone();
+        let conn = rocket::tokio::task::spawn_blocking(move || pool.get())
+            .await
+            .expect("Connection checkout panicked")?;
+        Ok(DbConn(conn))
+    }
+}
+
+impl Deref for DbConn {
+    type Target = MysqlConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DbConn {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let pool = match req.guard::<&State<DbPool>>().await {
+            Outcome::Success(pool) => pool,
+            _ => return Outcome::Failure((Status::InternalServerError, ()))
+        };
+
+        match DbConn::get(pool).await {
+            Ok(conn) => Outcome::Success(conn),
+            Err(_) => Outcome::Failure((Status::ServiceUnavailable, ()))
+        }
+    }
+}
+
 #[get("/logged_in")]
 pub fn logged_in(user: auth::guards::User) -> String {
     format!("Logged in as <b>{}</b> in course <b>{}</b>", user.name, user.course)
@@ -7,8 +7,14 @@ use smartbeans_backend::SETTINGS;
 #[rocket::main]
 async fn main() {
     // Run database migrations on startup
+    let pool = smartbeans_backend::database_pool();
     embed_migrations!();
-    embedded_migrations::run(&smartbeans_backend::database_connection()).unwrap();
+    embedded_migrations::run(&pool.get().expect("Failed to open database connection")).unwrap();
+
+    // Start evaluation workers and requeue submissions that were pending on shutdown
+    let hub = smartbeans_backend::events::Hub::default();
+    let queue = smartbeans_backend::evaluation::Queue::start(pool.clone(), hub.clone())
+        .expect("Failed to start evaluation queue");
 
     // Get rocket config from Settings file
     let mut config = Config::figment();
@@ -27,7 +33,30 @@ async fn main() {
             smartbeans_backend::route_get_info,
             smartbeans_backend::auth::lti::auth_lti,
             smartbeans_backend::auth::lti::put_lti_status,
+            smartbeans_backend::auth::lti13::get_lti13_login,
+            smartbeans_backend::auth::lti13::post_lti13_login,
+            smartbeans_backend::auth::lti13::auth_lti13,
+            smartbeans_backend::auth::lti_consumers::route_get_lti_consumers,
+            smartbeans_backend::auth::lti_consumers::route_post_lti_consumer,
+            smartbeans_backend::auth::lti_consumers::route_patch_lti_consumer,
+            smartbeans_backend::auth::lti_consumers::route_delete_lti_consumer,
+            smartbeans_backend::auth::roles::route_get_course_roles,
+            smartbeans_backend::auth::roles::route_put_course_role,
+            smartbeans_backend::auth::roles::route_delete_course_role,
+            smartbeans_backend::auth::admin::post_admin_token,
+            smartbeans_backend::auth::admin::route_put_admin,
+            smartbeans_backend::auth::admin::route_get_audit,
+            smartbeans_backend::course::management::route_get_courses,
+            smartbeans_backend::course::management::route_post_course,
+            smartbeans_backend::course::management::route_patch_course,
+            smartbeans_backend::course::management::route_delete_course,
+            smartbeans_backend::course::management::route_put_course_mapping,
+            smartbeans_backend::course::management::route_delete_course_mapping,
+            smartbeans_backend::course::enrollments::route_get_user_courses,
+            smartbeans_backend::course::enrollments::route_post_enroll,
             smartbeans_backend::auth::auth_debug,
+            smartbeans_backend::auth::put_active_course,
+            smartbeans_backend::auth::post_active_course,
             smartbeans_backend::auth::logout,
             smartbeans_backend::auth::api_token::post_api_token,
             smartbeans_backend::auth::api_token::get_api_token,
@@ -40,16 +69,44 @@ async fn main() {
             smartbeans_backend::course::tasks::route_get_tasks,
             smartbeans_backend::course::tasks::route_get_single_task,
             smartbeans_backend::course::tasks::route_post_task,
+            smartbeans_backend::course::tasks::route_get_admin_tasks,
+            smartbeans_backend::course::tasks::route_get_admin_task,
+            smartbeans_backend::course::tasks::route_patch_task,
+            smartbeans_backend::course::tasks::route_delete_task,
+            smartbeans_backend::course::tasks::route_put_course_task,
+            smartbeans_backend::course::tasks::route_delete_course_task,
+            smartbeans_backend::course::bundles::route_post_import,
+            smartbeans_backend::course::bundles::route_get_export,
+            smartbeans_backend::course::versions::route_get_versions,
+            smartbeans_backend::course::versions::route_get_version,
+            smartbeans_backend::course::versions::route_get_version_diff,
+            smartbeans_backend::course::versions::route_post_rollback,
+            smartbeans_backend::course::prerequisites::route_get_prerequisite_graph,
+            smartbeans_backend::course::groups::route_get_groups,
+            smartbeans_backend::course::groups::route_post_group,
+            smartbeans_backend::course::groups::route_patch_group,
+            smartbeans_backend::course::groups::route_delete_group,
             smartbeans_backend::course::submissions::route_get_all_submissions,
             smartbeans_backend::course::submissions::route_get_task_submissions,
             smartbeans_backend::course::submissions::route_get_single_submission,
             smartbeans_backend::course::submissions::route_post_submission,
+            smartbeans_backend::course::submissions::route_get_submission_result,
+            smartbeans_backend::evaluation::reevaluation::route_post_reevaluation,
+            smartbeans_backend::evaluation::reevaluation::route_get_reevaluations,
+            smartbeans_backend::evaluation::reevaluation::route_get_reevaluation,
+            smartbeans_backend::events::route_get_events,
+            smartbeans_backend::events::route_get_announcements,
+            smartbeans_backend::events::route_post_announcement,
             smartbeans_backend::user::route_get_meta,
             smartbeans_backend::user::put_display_name,
             smartbeans_backend::user::character::route_get_character,
             smartbeans_backend::user::character::route_patch_character,
             smartbeans_backend::logged_in
         ])
+        .register("/", catchers![smartbeans_backend::error::default_catcher])
+        .manage(pool)
+        .manage(queue)
+        .manage(hub)
         .attach(rocket_dyn_templates::Template::fairing())
         .launch()
         .await
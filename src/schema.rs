@@ -1,3 +1,23 @@
+table! {
+    adminAudit (id) {
+        id -> Integer,
+        admin -> Varchar,
+        method -> Varchar,
+        route -> Text,
+        timestamp -> Bigint,
+    }
+}
+
+table! {
+    announcements (id) {
+        id -> Integer,
+        course -> Varchar,
+        author -> Varchar,
+        message -> Text,
+        timestamp -> Bigint,
+    }
+}
+
 table! {
     courseMapping (studipId) {
         studipId -> Varchar,
@@ -10,6 +30,8 @@ table! {
         name -> Varchar,
         title -> Text,
         config -> Text,
+        archived -> Bool,
+        enrollmentKey -> Nullable<Varchar>,
     }
 }
 
@@ -20,6 +42,68 @@ table! {
         tags -> Text,
         orderBy -> Integer,
         prerequisites -> Text,
+        visibleFrom -> Nullable<Bigint>,
+        deadline -> Nullable<Bigint>,
+        lateUntil -> Nullable<Bigint>,
+        groupId -> Nullable<Integer>,
+    }
+}
+
+table! {
+    enrollments (username, course) {
+        username -> Varchar,
+        course -> Varchar,
+        role -> Varchar,
+        enrolledAt -> Bigint,
+    }
+}
+
+table! {
+    lti13Logins (state) {
+        state -> Varchar,
+        nonce -> Varchar,
+        issuer -> Text,
+        clientId -> Text,
+        expirationTime -> Bigint,
+    }
+}
+
+table! {
+    ltiConsumers (consumerKey) {
+        consumerKey -> Varchar,
+        secret -> Text,
+        enabled -> Bool,
+        defaultCourse -> Nullable<Varchar>,
+        usernamePrefix -> Varchar,
+    }
+}
+
+table! {
+    ltiNonces (consumerKey, nonce) {
+        consumerKey -> Varchar,
+        nonce -> Varchar,
+        expirationTime -> Bigint,
+    }
+}
+
+table! {
+    ltiOutcomes (username, course) {
+        username -> Varchar,
+        course -> Varchar,
+        consumerKey -> Varchar,
+        serviceUrl -> Text,
+        sourcedid -> Text,
+        lastGrade -> Nullable<Float>,
+    }
+}
+
+table! {
+    reevaluations (id) {
+        id -> Integer,
+        course -> Varchar,
+        taskid -> Nullable<Integer>,
+        admin -> Varchar,
+        timestamp -> Bigint,
     }
 }
 
@@ -30,6 +114,20 @@ table! {
         courseName -> Varchar,
         expirationTime -> Bigint,
         tokenName -> Nullable<Text>,
+        scope -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    submissionHistory (id) {
+        id -> Integer,
+        submission -> Integer,
+        reevaluation -> Integer,
+        resultType -> Varchar,
+        simplified -> Text,
+        details -> Text,
+        score -> Float,
+        taskVersion -> Nullable<Integer>,
     }
 }
 
@@ -45,6 +143,20 @@ table! {
         simplified -> Text,
         details -> Text,
         score -> Float,
+        taskVersion -> Nullable<Integer>,
+        late -> Bool,
+    }
+}
+
+table! {
+    taskGroups (id) {
+        id -> Integer,
+        course -> Varchar,
+        title -> Text,
+        description -> Text,
+        orderBy -> Integer,
+        visibleFrom -> Nullable<Bigint>,
+        passThreshold -> Float,
     }
 }
 
@@ -55,6 +167,22 @@ table! {
         solution -> Text,
         lang -> Text,
         tests -> Text,
+        version -> Integer,
+    }
+}
+
+table! {
+    taskVersions (id) {
+        id -> Integer,
+        taskid -> Integer,
+        version -> Integer,
+        taskDescription -> Text,
+        solution -> Text,
+        lang -> Text,
+        tests -> Text,
+        courses -> Text,
+        author -> Varchar,
+        timestamp -> Bigint,
     }
 }
 
@@ -65,15 +193,27 @@ table! {
         password -> Nullable<Text>,
         ltiEnabled -> Bool,
         charData -> Text,
+        isAdmin -> Bool,
     }
 }
 
 allow_tables_to_appear_in_same_query!(
+    adminAudit,
+    announcements,
     courseMapping,
     courses,
     courseTask,
+    enrollments,
+    lti13Logins,
+    ltiConsumers,
+    ltiNonces,
+    ltiOutcomes,
+    reevaluations,
     sessions,
+    submissionHistory,
     submissions,
+    taskGroups,
     tasks,
+    taskVersions,
     users,
 );
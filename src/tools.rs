@@ -1,6 +1,18 @@
 use rocket::data::ToByteUnit;
+use rand::{thread_rng, Rng, distributions::Alphanumeric};
+use rocket::http::Status;
+use crate::error::Error;
+use diesel::prelude::*;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Returns the AUTO_INCREMENT id of the last row inserted on this connection.
+pub fn last_insert_id(conn: &MysqlConnection) -> Result<i32, Error> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{BigInt, Unsigned};
+    Ok(diesel::select(sql::<Unsigned<BigInt>>("LAST_INSERT_ID()"))
+        .get_result::<u64>(conn)? as i32)
+}
+
 /// Returns the current time in seconds since 1970-01-01.
 pub fn epoch() -> i64 {
     SystemTime::now()
@@ -11,10 +23,95 @@ pub fn epoch() -> i64 {
 
 /// Convert rocket::Data into String
 /// I hope 2 MiB will be enough for everything.
-pub async fn data_to_string(data: rocket::Data<'_>) -> String {
-    data.open(2.mebibytes())
+pub async fn data_to_string(data: rocket::Data<'_>) -> Result<String, Error> {
+    Ok(data.open(2.mebibytes())
         .into_string()
         .await
-        .unwrap()
-        .into_inner()
-}
\ No newline at end of file
+        .or(Err(Status::BadRequest))?
+        .into_inner())
+}
+/// Returns a random alphanumeric string, e.g. for tokens and nonces.
+pub fn random_string(length: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+#[cfg(test)]
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Minimal HTTP server for tests against external services (LMS, sandbox, ...).
+/// `handler` returns status code and body for each request. Returns the base
+/// URL of the server and all requests received so far.
+#[cfg(test)]
+pub async fn mock_http_server<F>(handler: F) -> (String, std::sync::Arc<std::sync::Mutex<Vec<MockRequest>>>)
+    where F: Fn(&MockRequest) -> (u16, String) + Send + Sync + 'static
+{
+    use rocket::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use std::sync::{Arc, Mutex};
+
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler = Arc::new(handler);
+
+    let received = requests.clone();
+    rocket::tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (handler, received) = (handler.clone(), received.clone());
+
+            rocket::tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let mut request_line = line.split_whitespace().map(str::to_string);
+                let (method, path) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+
+                let mut headers = Vec::new();
+                loop {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    match line.trim_end().split_once(':') {
+                        Some((key, value)) => headers.push((key.to_string(), value.trim().to_string())),
+                        None => break
+                    }
+                }
+
+                let mut request = MockRequest { method, path, headers, body: String::new() };
+                let length = request.header("content-length").map_or(0, |l| l.parse().unwrap());
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                request.body = String::from_utf8(body).unwrap();
+
+                let (status, body) = handler(&request);
+                received.lock().unwrap().push(request);
+
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, body.len(), body
+                );
+                stream.write_all(response.as_bytes()).await.ok();
+            });
+        }
+    });
+
+    (url, requests)
+}
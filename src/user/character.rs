@@ -3,15 +3,17 @@ use rocket::http::Status;
 use diesel::prelude::*;
 use serde::{Serialize, Deserialize, Deserializer};
 use crate::auth::guards;
+use crate::DbConn;
+use crate::error::Error;
 
 #[get("/user/character")]
-pub fn route_get_character(user: guards::User) -> Json<Character> {
-    Json(get_character_data(&user.name))
+pub fn route_get_character(user: guards::Session, conn: DbConn) -> Result<Json<Character>, Error> {
+    Ok(Json(get_character_data(&conn, &user.name)?))
 }
 
 #[patch("/user/character", data = "<patch>")]
-pub fn route_patch_character(user: guards::User, patch: Json<CharacterPatch>) -> Status {
-    let character = get_character_data(&user.name);
+pub fn route_patch_character(user: guards::Session, patch: Json<CharacterPatch>, conn: DbConn) -> Result<Status, Error> {
+    let character = get_character_data(&conn, &user.name)?;
 
     let body_color = match &patch.bodyColor {
         Some(val) => val.to_owned(),
@@ -46,15 +48,14 @@ pub fn route_patch_character(user: guards::User, patch: Json<CharacterPatch>) ->
         pantsId: pants_id
     };
 
-    let patched_string = serde_json::to_string(&patched_character).unwrap();
+    let patched_string = serde_json::to_string(&patched_character)?;
 
     use crate::schema::users;
     diesel::update(users::table.filter(users::username.eq(user.name)))
         .set(users::charData.eq(patched_string))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(&*conn)?;
 
-    Status::Ok
+    Ok(Status::Ok)
 }
 
 #[allow(non_snake_case)]
@@ -91,11 +92,10 @@ fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
     Deserialize::deserialize(deserializer).map(Some)
 }
 
-fn get_character_data(user: &str) -> Character {
+fn get_character_data(conn: &MysqlConnection, user: &str) -> Result<Character, Error> {
     use crate::schema::users;
     let character = users::table.filter(users::username.eq(user))
         .select(users::charData)
-        .first::<String>(&crate::database_connection())
-        .expect("Database error");
-    serde_json::from_str(&character).unwrap()
+        .first::<String>(conn)?;
+    Ok(serde_json::from_str(&character)?)
 }
\ No newline at end of file
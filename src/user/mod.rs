@@ -3,36 +3,40 @@ use rocket::http::Status;
 use serde_json::Value;
 use diesel::prelude::*;
 use crate::auth::guards;
+use crate::DbConn;
+use crate::error::Error;
 
 pub mod character;
 
 #[get("/user/meta")]
-pub fn route_get_meta(user: guards::User) -> Result<Json<Value>, Status> {
+pub fn route_get_meta(user: guards::User, conn: DbConn) -> Result<Json<Value>, Error> {
     use crate::schema::users;
     let (display_name, password, lti_enabled) = users::table.filter(users::username.eq(&user.name))
         .select((users::displayName, users::password, users::ltiEnabled))
-        .first::<(String, Option<String>, bool)>(&crate::database_connection())
-        .expect("Database error");
+        .first::<(String, Option<String>, bool)>(&*conn)?;
+    let role = crate::auth::roles::course_role(&conn, &user.name, &user.course)?;
+    let courses = crate::course::enrollments::user_courses(&conn, &user.name)?;
 
     Ok(Json(json!({
         "username": user.name,
         "displayName": display_name,
         "passwordSet": password.is_some(),
         "ltiEnabled": lti_enabled,
-        "activeCourse": user.course
+        "activeCourse": user.course,
+        "role": role,
+        "courses": courses
     })))
 }
 
 #[put("/user/displayName", data = "<data>")]
-pub fn put_display_name(user: guards::User, data: Json<Value>) -> Result<Status, Status> {
+pub fn put_display_name(user: guards::Session, data: Json<Value>, conn: DbConn) -> Result<Status, Error> {
     let display_name = data["displayName"].as_str()
         .ok_or(Status::BadRequest)?;
 
     use crate::schema::users;
     diesel::update(users::table.filter(users::username.eq(user.name)))
         .set(users::displayName.eq(display_name))
-        .execute(&crate::database_connection())
-        .expect("Database error");
+        .execute(&*conn)?;
 
     Ok(Status::Ok)
 }
\ No newline at end of file